@@ -0,0 +1,962 @@
+use regex::bytes::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+use crate::{
+    options::{InvalidUtf8, Options, WordMode},
+    Statistics,
+};
+
+/// the size of the blocks that inputs are read in
+pub const BLOCK_SIZE: usize = 64 * 1024;
+
+/// A single-pass counter that is fed an input one block of bytes at a time
+///
+/// everything that spans a block boundary (a partial utf-8 sequence,
+/// whether we're in the middle of a word, the length of the current line)
+/// is carried in here, so memory use doesn't depend on the length of lines
+pub struct Counter {
+    statistics: Statistics,
+    invalid: InvalidUtf8,
+    tab_width: u64,
+    partial_lines: bool,
+    word_mode: WordMode,
+    word_delimiters: Vec<char>,
+    word_regex: Option<Regex>,
+    graphemes: bool,
+
+    // utf-8 decoder state
+    //
+    // `need` is the number of continuation bytes we're still waiting for
+    // and `pending` is the number of bytes of the sequence seen so far
+    //
+    // `lower..=upper` is the range that the next continuation byte must be in,
+    // which is how overlong encodings, surrogates and code points past U+10FFFF
+    // are rejected, same as `std::str::from_utf8`
+    code_point: u32,
+    need: u8,
+    pending: u8,
+    lower: u8,
+    upper: u8,
+
+    in_word: bool,
+
+    /// the text since the last separator for `--word-mode unicode`,
+    /// which is split into words once it ends
+    run: String,
+
+    /// how long `run` can get before the words in it that are definitely done are counted
+    run_limit: usize,
+
+    /// the text since the last grapheme cluster that's known to be complete, for `--graphemes`
+    text: String,
+
+    /// how long `text` can get before the clusters in it are counted
+    text_limit: usize,
+
+    /// the current line for `--word-regex`, which is matched once it ends
+    ///
+    /// note: so a file without newlines is held in memory whole
+    line: Vec<u8>,
+
+    /// the display width of the current line so far, in columns
+    line_length: u64,
+
+    /// whether there's anything after the last newline
+    partial: bool,
+
+    /// whether the last byte was a CR, which makes the next one either a CRLF or a bare CR
+    after_cr: bool,
+
+    // what's needed to merge this with counts of neighbouring parts of the input,
+    // see `Chunk`
+    started: bool,
+    starts_in_word: bool,
+    starts_with_lf: bool,
+    newline: bool,
+    head: u64,
+}
+
+/// The counts for one part of an input, counted on its own
+///
+/// words and lines can span the edges of the part,
+/// so this also keeps what was seen at those edges
+/// to stitch neighbouring parts back together with `merge`
+///
+/// note: parts must be split at the start of a character (see `split_point`)
+/// so that no utf-8 sequence is spread over two parts,
+/// and after a newline when line lengths are wanted (see `line_split_point`)
+#[derive(Debug)]
+pub struct Chunk {
+    /// `lines` and `max_line_length` only cover lines that start and end in this part
+    statistics: Statistics,
+
+    /// whether there's any valid character at all,
+    /// since invalid sequences neither start nor end words (same as GNU wc)
+    /// and a part with nothing else leaves the word going on from before as it is
+    started: bool,
+
+    /// whether the first character isn't whitespace
+    starts_in_word: bool,
+
+    /// whether the last character isn't whitespace
+    ends_in_word: bool,
+
+    /// whether the first byte is an LF, which was counted as a bare one
+    starts_with_lf: bool,
+
+    /// whether the last byte is a CR, which was counted as a bare one
+    ends_with_cr: bool,
+
+    /// whether a line ends in this part at all,
+    /// if there isn't `head` and `tail` are the same line
+    newline: bool,
+
+    /// the length of the line before the first newline
+    head: u64,
+
+    /// the length of the line after the last newline
+    tail: u64,
+
+    /// whether there's anything after the last newline,
+    /// since a line can be there but take up no columns
+    partial: bool,
+
+    /// whether that counts as a line, see `--count-partial-lines`
+    partial_lines: bool,
+}
+
+impl Counter {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            statistics: Statistics::new(),
+            invalid: options.invalid_utf8,
+            tab_width: options.tab_width,
+            partial_lines: options.count_partial_lines,
+            word_mode: options.word_mode,
+            word_delimiters: options
+                .word_delimiters
+                .as_deref()
+                .map_or_else(Vec::new, |delimiters| delimiters.chars().collect()),
+            word_regex: options.word_regex.clone(),
+            graphemes: options.graphemes,
+            code_point: 0,
+            need: 0,
+            pending: 0,
+            lower: 0x80,
+            upper: 0xBF,
+            in_word: false,
+            run: String::new(),
+            run_limit: BLOCK_SIZE,
+            text: String::new(),
+            text_limit: BLOCK_SIZE,
+            line: Vec::new(),
+            line_length: 0,
+            partial: false,
+            after_cr: false,
+            started: false,
+            starts_in_word: false,
+            starts_with_lf: false,
+            newline: false,
+            head: 0,
+        }
+    }
+
+    // note: per-input counts are plain `u64` additions
+    // since no input can be long enough to overflow them
+    pub fn update(&mut self, block: &[u8]) {
+        if self.statistics.bytes == 0 && block.first() == Some(&b'\n') {
+            self.starts_with_lf = true;
+        }
+
+        self.statistics.bytes += block.len() as u64;
+
+        for &byte in block {
+            self.byte(byte);
+        }
+    }
+
+    pub fn finish(self) -> Statistics {
+        self.finish_chunk().finish()
+    }
+
+    /// like `finish`, but keeps the edges so that the result can be merged
+    pub fn finish_chunk(mut self) -> Chunk {
+        // the input ended in the middle of a sequence
+        if self.need > 0 {
+            self.invalid_sequence();
+        }
+
+        if self.after_cr {
+            self.statistics.cr += 1;
+        }
+
+        self.count_run(false);
+        self.count_graphemes(false);
+        self.match_line();
+
+        Chunk {
+            statistics: self.statistics,
+            started: self.started,
+            starts_in_word: self.starts_in_word,
+            ends_in_word: self.in_word,
+            starts_with_lf: self.starts_with_lf,
+            ends_with_cr: self.after_cr,
+            newline: self.newline,
+            head: if self.newline {
+                self.head
+            } else {
+                self.line_length
+            },
+            tail: self.line_length,
+            partial: self.partial,
+            partial_lines: self.partial_lines,
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.partial = true;
+
+        if self.word_regex.is_some() {
+            self.line.push(byte);
+
+            if byte == b'\n' {
+                self.match_line();
+            }
+        }
+
+        let after_cr = std::mem::replace(&mut self.after_cr, byte == b'\r');
+
+        if after_cr && byte != b'\n' {
+            self.statistics.cr += 1;
+        }
+
+        if self.need > 0 {
+            if (self.lower..=self.upper).contains(&byte) {
+                self.code_point = (self.code_point << 6) | (byte & 0x3F) as u32;
+                self.need -= 1;
+                self.pending += 1;
+                self.lower = 0x80;
+                self.upper = 0xBF;
+
+                if self.need == 0 {
+                    self.pending = 0;
+
+                    // the range checks above guarantee a valid scalar value
+                    let c = char::from_u32(self.code_point).unwrap_or(char::REPLACEMENT_CHARACTER);
+
+                    self.char(c);
+                }
+
+                return;
+            }
+
+            // the sequence was cut short, so it's invalid
+            // and `byte` has to be looked at on its own
+            self.invalid_sequence();
+        }
+
+        match byte {
+            0x00..=0x7F => {
+                self.char(byte as char);
+
+                if byte == b'\n' {
+                    if after_cr {
+                        self.statistics.crlf += 1;
+                    } else {
+                        self.statistics.lf += 1;
+                    }
+
+                    self.statistics.lines += 1;
+                    self.partial = false;
+                    self.end_line();
+                } else if byte == b'\r' || byte == b'\x0C' {
+                    // like GNU wc, CRs and form feeds go back to the start of the line,
+                    // so they end its length but not the line itself
+                    self.end_line();
+                }
+            }
+            0xC2..=0xDF => self.start(byte & 0x1F, 1, 0x80, 0xBF),
+            0xE0 => self.start(byte & 0x0F, 2, 0xA0, 0xBF),
+            0xE1..=0xEC | 0xEE..=0xEF => self.start(byte & 0x0F, 2, 0x80, 0xBF),
+            0xED => self.start(byte & 0x0F, 2, 0x80, 0x9F),
+            0xF0 => self.start(byte & 0x07, 3, 0x90, 0xBF),
+            0xF1..=0xF3 => self.start(byte & 0x07, 3, 0x80, 0xBF),
+            0xF4 => self.start(byte & 0x07, 3, 0x80, 0x8F),
+            // stray continuation bytes and bytes that never appear in utf-8
+            _ => {
+                self.pending = 1;
+                self.invalid_sequence();
+            }
+        }
+    }
+
+    fn start(&mut self, bits: u8, need: u8, lower: u8, upper: u8) {
+        self.code_point = bits as u32;
+        self.need = need;
+        self.pending = 1;
+        self.lower = lower;
+        self.upper = upper;
+    }
+
+    fn invalid_sequence(&mut self) {
+        let chars = self.invalid.chars(self.pending as usize);
+
+        self.statistics.chars += chars as u64;
+
+        // they're as many clusters as they are characters
+        if self.graphemes {
+            for _ in 0..chars {
+                self.grapheme_char(char::REPLACEMENT_CHARACTER);
+            }
+        }
+
+        self.need = 0;
+        self.pending = 0;
+        self.lower = 0x80;
+        self.upper = 0xBF;
+    }
+
+    fn char(&mut self, c: char) {
+        self.statistics.chars += 1;
+
+        if self.graphemes {
+            self.grapheme_char(c);
+        }
+
+        self.line_length += match c {
+            ' '..='~' => 1,
+            '\t' => self.tab_width - self.line_length % self.tab_width,
+            // control characters (including the newline itself) and combining marks are 0,
+            // wide characters like CJK are 2
+            //
+            // note: invalid sequences never get here, so like GNU wc they're 0 too,
+            // since there's no telling how a terminal would show them
+            c => c.width().unwrap_or(0) as u64,
+        };
+
+        let separator = self.word_delimiters.contains(&c)
+            || match self.word_mode {
+                // what isspace() is in the C locale
+                WordMode::Posix => matches!(c, ' ' | '\t'..='\r'),
+                WordMode::Whitespace | WordMode::Unicode => c.is_whitespace(),
+            };
+
+        self.word(c, separator);
+    }
+
+    fn word(&mut self, c: char, separator: bool) {
+        match self.word_mode {
+            // words are matched a line at a time instead
+            _ if self.word_regex.is_some() => {}
+            WordMode::Unicode if separator => self.count_run(false),
+            WordMode::Unicode => {
+                self.run.push(c);
+
+                if self.run.len() >= self.run_limit {
+                    self.count_run(true);
+                }
+            }
+            WordMode::Whitespace | WordMode::Posix => {
+                if !self.started {
+                    self.started = true;
+                    self.starts_in_word = !separator;
+                }
+
+                if separator {
+                    self.in_word = false;
+                } else if !self.in_word {
+                    self.statistics.words += 1;
+                    self.in_word = true;
+                }
+            }
+        }
+    }
+
+    /// Counts the words in `run` by their UAX #29 boundaries,
+    /// where only the parts with letters or digits in them are words (not punctuation)
+    ///
+    /// with `partial` the run isn't over yet, so the last two parts are kept:
+    /// the last one might go on, and the one before might still be joined with it
+    /// (like `c.` and `d` in `c.d`)
+    fn count_run(&mut self, partial: bool) {
+        let is_word = |part: &str| part.chars().any(char::is_alphanumeric);
+
+        // the last two parts, as where they start and whether they're words
+        let mut last = [(0, false); 2];
+
+        for (i, part) in self.run.split_word_bound_indices() {
+            if last[0].1 {
+                self.statistics.words += 1;
+            }
+
+            last = [last[1], (i, is_word(part))];
+        }
+
+        if partial {
+            self.run.drain(..last[0].0);
+
+            // a single huge part isn't looked at again for every character added to it
+            self.run_limit = BLOCK_SIZE.max(self.run.len() * 2);
+        } else {
+            self.statistics.words += last.iter().filter(|(_, word)| *word).count() as u64;
+
+            self.run.clear();
+            self.run_limit = BLOCK_SIZE;
+        }
+    }
+
+    fn grapheme_char(&mut self, c: char) {
+        self.text.push(c);
+
+        if self.text.len() >= self.text_limit {
+            self.count_graphemes(true);
+        }
+    }
+
+    /// Counts the grapheme clusters in `text`
+    ///
+    /// with `partial` there's more text to come, so the last cluster is kept
+    /// since more characters might still join it
+    ///
+    /// note: a boundary only depends on what comes before it and the character right after,
+    /// so every boundary before the last cluster is final
+    fn count_graphemes(&mut self, partial: bool) {
+        let mut last = None;
+
+        for (i, _) in self.text.grapheme_indices(true) {
+            if last.is_some() {
+                self.statistics.graphemes += 1;
+            }
+
+            last = Some(i);
+        }
+
+        if partial {
+            self.text.drain(..last.unwrap_or(0));
+
+            // a single huge cluster isn't looked at again for every character added to it
+            self.text_limit = BLOCK_SIZE.max(self.text.len() * 2);
+        } else {
+            if last.is_some() {
+                self.statistics.graphemes += 1;
+            }
+
+            self.text.clear();
+            self.text_limit = BLOCK_SIZE;
+        }
+    }
+
+    /// Counts the matches of `--word-regex` in `line`
+    fn match_line(&mut self) {
+        if let Some(regex) = &self.word_regex {
+            let matches = regex.find_iter(&self.line).filter(|m| !m.is_empty());
+
+            self.statistics.words += matches.count() as u64;
+        }
+
+        self.line.clear();
+    }
+
+    /// Finishes the length of the current line
+    ///
+    /// note: lines are counted by the caller,
+    /// since CRs and form feeds end a line for its length without being newlines
+    fn end_line(&mut self) {
+        // the first line might have started in an earlier part of the input,
+        // so its length is kept aside until we know
+        if self.newline {
+            self.statistics.max_line_length = self.statistics.max_line_length.max(self.line_length);
+        } else {
+            self.newline = true;
+            self.head = self.line_length;
+        }
+
+        self.line_length = 0;
+    }
+}
+
+impl Chunk {
+    /// Combines this part with the one that immediately follows it
+    pub fn merge(self, next: Chunk) -> Chunk {
+        let mut statistics = self.statistics + next.statistics;
+
+        // a word that spans the boundary was counted on both sides
+        if self.ends_in_word && next.starts_in_word {
+            statistics.words -= 1;
+        }
+
+        // so was a CRLF, as a bare CR and a bare LF
+        if self.ends_with_cr && next.starts_with_lf {
+            statistics.cr -= 1;
+            statistics.lf -= 1;
+            statistics.crlf += 1;
+        }
+
+        // the line that spans the boundary
+        let middle = self.tail + next.head;
+
+        if next.newline {
+            statistics.max_line_length = statistics.max_line_length.max(middle);
+        }
+
+        Chunk {
+            statistics,
+            started: self.started || next.started,
+            starts_in_word: if self.started {
+                self.starts_in_word
+            } else {
+                next.starts_in_word
+            },
+            ends_in_word: if next.started {
+                next.ends_in_word
+            } else {
+                self.ends_in_word
+            },
+            starts_with_lf: self.starts_with_lf,
+            ends_with_cr: next.ends_with_cr,
+            newline: self.newline || next.newline,
+            head: if self.newline { self.head } else { middle },
+            tail: if next.newline { next.tail } else { middle },
+            partial: next.partial || (self.partial && !next.newline),
+            partial_lines: self.partial_lines,
+        }
+    }
+
+    /// The statistics for the whole input, once every part has been merged
+    pub fn finish(self) -> Statistics {
+        let mut statistics = self.statistics;
+
+        // the first line is complete now that there's nothing before it
+        if self.newline {
+            statistics.max_line_length = statistics.max_line_length.max(self.head);
+        }
+
+        // the last line doesn't end in a newline,
+        // so it isn't a line as far as POSIX is concerned (but still has a length)
+        if self.partial {
+            statistics.missing_newline = 1;
+            statistics.max_line_length = statistics.max_line_length.max(self.tail);
+
+            if self.partial_lines {
+                statistics.lines += 1;
+            }
+        }
+
+        let endings = [statistics.lf, statistics.crlf, statistics.cr];
+
+        if endings.iter().filter(|&&count| count > 0).count() > 1 {
+            statistics.mixed_endings = 1;
+        }
+
+        statistics
+    }
+}
+
+/// Moves `offset` forward to just after a newline, given the bytes from `offset` on in `window`
+///
+/// when lines are measured by their width, tabs depend on where the line starts,
+/// so lines can't be split between parts
+pub fn line_split_point(window: &[u8]) -> Option<usize> {
+    window.iter().position(|&byte| byte == b'\n').map(|i| i + 1)
+}
+
+/// Moves `offset` forward to the start of a character,
+/// given the bytes from `offset - 3` up to `offset + 3` in `window`
+/// and where `offset` is in it
+///
+/// it's the first byte that isn't a continuation byte,
+/// or one that comes after three continuation bytes
+/// since no sequence can still be going at that point
+pub fn split_point(window: &[u8], offset: usize) -> usize {
+    let continuation = |byte: &u8| byte & 0xC0 == 0x80;
+
+    (offset..window.len())
+        .find(|&i| {
+            !continuation(&window[i]) || (i >= 3 && window[i - 3..i].iter().all(continuation))
+        })
+        .unwrap_or(window.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    /// counts `blocks` as one input, like `wc2 <args>` would
+    fn count(args: &[&str], blocks: &[&[u8]]) -> Statistics {
+        let mut options = Options::parse_from(["wc2"].iter().chain(args));
+        options.select_counts();
+
+        let mut counter = Counter::new(&options);
+
+        for block in blocks {
+            counter.update(block);
+        }
+
+        counter.finish()
+    }
+
+    // each one is invalid in a different way, and is counted like `String::from_utf8_lossy` does
+    const INVALID: &[&[u8]] = &[
+        // overlong forms
+        b"\xC0\x80",
+        b"\xC1\xBF",
+        b"\xE0\x80\x80",
+        b"\xE0\x9F\xBF",
+        b"\xF0\x80\x80\x80",
+        b"\xF0\x8F\xBF\xBF",
+        // surrogates
+        b"\xED\xA0\x80",
+        b"\xED\xBF\xBF",
+        // above U+10FFFF
+        b"\xF4\x90\x80\x80",
+        b"\xF5\x80\x80\x80",
+        b"\xFF",
+        // cut short, at the end and before something else
+        b"\xE2\x82",
+        b"\xF0\x9F\x98",
+        b"\xE2\x82a",
+        b"\xF0\x9F\x98\xE2\x82\xAC",
+        // stray continuation bytes
+        b"\x80",
+        b"a\xBF\xBFb",
+    ];
+
+    #[test]
+    fn valid_utf8() {
+        let input = "aé€😀\u{10FFFF}".as_bytes();
+
+        assert_eq!(count(&[], &[input]).chars, 5);
+        assert_eq!(count(&[], &[input]).bytes, input.len() as u64);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        for input in INVALID {
+            let lossy = String::from_utf8_lossy(input);
+            let replaced = lossy.chars().count() as u64;
+            let skipped = lossy
+                .chars()
+                .filter(|&c| c != char::REPLACEMENT_CHARACTER)
+                .count() as u64;
+
+            assert_eq!(
+                count(&["--invalid-utf8=replace"], &[input]).chars,
+                replaced,
+                "{input:x?}"
+            );
+            assert_eq!(
+                count(&["--invalid-utf8=skip"], &[input]).chars,
+                skipped,
+                "{input:x?}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_bytes() {
+        assert_eq!(count(&["--invalid-utf8=byte"], &[b"\xE2\x82a"]).chars, 3);
+        assert_eq!(count(&["--invalid-utf8=byte"], &[b"\xED\xA0\x80"]).chars, 3);
+        assert_eq!(count(&["--invalid-utf8=byte"], &[b"\xF0\x9F\x98"]).chars, 3);
+    }
+
+    #[test]
+    fn sequences_across_blocks() {
+        let inputs = [b"\xE2\x82\xAC".as_slice(), "a😀b".as_bytes()]
+            .into_iter()
+            .chain(INVALID.iter().copied());
+
+        for input in inputs {
+            let whole = count(&["--invalid-utf8=replace"], &[input]);
+
+            for i in 0..=input.len() {
+                let (a, b) = input.split_at(i);
+                let split = count(&["--invalid-utf8=replace"], &[a, b]);
+
+                assert_eq!(split.chars, whole.chars, "{input:x?} split at {i}");
+            }
+        }
+    }
+
+    /// counts `input` in parts split at `splits`, merged back together
+    fn count_parts(args: &[&str], input: &[u8], splits: &[usize]) -> Statistics {
+        let mut options = Options::parse_from(["wc2"].iter().chain(args));
+        options.select_counts();
+
+        let edges: Vec<usize> = [0]
+            .into_iter()
+            .chain(splits.iter().copied())
+            .chain([input.len()])
+            .collect();
+        let parts = edges.windows(2).map(|edge| {
+            let (start, end) = (edge[0], edge[1]);
+            let mut counter = Counter::new(&options);
+
+            counter.update(&input[start..end]);
+            counter.finish_chunk()
+        });
+
+        parts.reduce(Chunk::merge).unwrap().finish()
+    }
+
+    #[test]
+    fn merged_parts() {
+        // note: no tabs, their width depends on where the line starts
+        let inputs: &[&[u8]] = &[
+            b"foo bar\r\nbaz  qux\n",
+            b"a\r\n\r\nb\rc\r",
+            b"one two",
+            b"x \xFF y\r\n\xFF\xE2\x82",
+            "h\u{e9}llo \u{4e16}\u{754c}\n\u{20ac}".as_bytes(),
+            b"\n\n a",
+        ];
+        let args: &[&[&str]] = &[
+            &["-lwmcL", "--line-endings", "--missing-newline"],
+            &["-lwL", "--count-partial-lines"],
+            &["-w", "--word-mode=posix", "--word-delimiters=/"],
+        ];
+
+        for input in inputs {
+            // only at the start of characters and never empty, like `count_chunked` does
+            let splits = (1..input.len()).filter(|&i| split_point(input, i) == i);
+
+            for args in args {
+                let whole = count(args, &[input]);
+
+                for i in splits.clone() {
+                    assert_eq!(
+                        count_parts(args, input, &[i]),
+                        whole,
+                        "{input:x?} split at {i}"
+                    );
+
+                    for j in splits.clone().filter(|&j| j > i) {
+                        let split = count_parts(args, input, &[i, j]);
+
+                        assert_eq!(split, whole, "{input:x?} split at {i} and {j}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn split_points() {
+        let euro = b"a\xE2\x82\xACb";
+
+        assert_eq!(split_point(euro, 0), 0);
+        assert_eq!(split_point(euro, 2), 4);
+        assert_eq!(split_point(euro, 4), 4);
+
+        // no sequence is longer than four bytes, so one can always be found
+        assert_eq!(split_point(&[0x80; 7], 3), 3);
+        assert_eq!(split_point(&[0x80; 7], 5), 5);
+        assert_eq!(split_point(&[0x80; 2], 0), 2);
+
+        assert_eq!(line_split_point(b"ab\ncd"), Some(3));
+        assert_eq!(line_split_point(b"abcd"), None);
+    }
+
+    fn words(args: &[&str], input: &str) -> u64 {
+        count(args, &[input.as_bytes()]).words
+    }
+
+    #[test]
+    fn whitespace_words() {
+        let input = "one  two\tthree\u{3000}four\u{a0}five\n\nsix";
+
+        assert_eq!(words(&[], input), 6);
+        // only ascii whitespace separates words, so the others are part of them
+        assert_eq!(words(&["--word-mode=posix"], input), 4);
+        assert_eq!(words(&["--word-mode=posix"], "a\x0bb\x0cc\rd"), 4);
+    }
+
+    #[test]
+    fn unicode_words() {
+        let unicode = |input| words(&["--word-mode=unicode"], input);
+
+        assert_eq!(unicode("don't"), 1);
+        assert_eq!(unicode("foo-bar"), 2);
+        assert_eq!(unicode("a.b"), 1);
+        assert_eq!(unicode("3.14"), 1);
+        assert_eq!(unicode("\u{4f60}\u{597d}\u{4e16}\u{754c}"), 4);
+        assert_eq!(unicode("hello, world!"), 2);
+        assert_eq!(unicode("... -- !!"), 0);
+    }
+
+    #[test]
+    fn delimited_words() {
+        let args = ["--word-delimiters=-/"];
+
+        assert_eq!(words(&args, "a-b/c d--e"), 5);
+        assert_eq!(words(&args, "-/-"), 0);
+    }
+
+    #[test]
+    fn regex_words() {
+        let args = ["--word-regex=[0-9]+"];
+
+        assert_eq!(words(&args, "a1 22 b333\n4"), 4);
+        // matches don't go past the end of a line
+        assert_eq!(words(&args, "1\n2\r\n3"), 3);
+        assert_eq!(words(&args, "none\n"), 0);
+    }
+
+    #[test]
+    fn long_words() {
+        let long = "a".repeat(3 * BLOCK_SIZE);
+        let dotted = "a.".repeat(BLOCK_SIZE) + "a";
+
+        for input in [
+            format!("{long} b"),
+            format!("{dotted} b"),
+            format!("x {long}."),
+        ] {
+            // in blocks that don't line up with anything
+            let blocks = input.as_bytes().chunks(4093).collect::<Vec<_>>();
+
+            assert_eq!(count(&[], &blocks).words, 2);
+            assert_eq!(count(&["--word-mode=unicode"], &blocks).words, 2);
+        }
+    }
+
+    fn graphemes(blocks: &[&[u8]]) -> u64 {
+        count(&["--graphemes"], blocks).graphemes
+    }
+
+    #[test]
+    fn grapheme_clusters() {
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{200d}\u{1f466}";
+        let flags = "\u{1f1ef}\u{1f1f5}\u{1f1fa}\u{1f1f8}";
+
+        assert_eq!(graphemes(&[family.as_bytes()]), 1);
+        assert_eq!(graphemes(&[flags.as_bytes()]), 2);
+        assert_eq!(graphemes(&["e\u{301}a\u{308}\u{323}".as_bytes()]), 2);
+        assert_eq!(graphemes(&[b"a\r\nb\n\r"]), 5);
+        assert_eq!(graphemes(&[b""]), 0);
+    }
+
+    #[test]
+    fn clusters_across_blocks() {
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}".as_bytes();
+        let (a, b) = family.split_at(5);
+
+        assert_eq!(graphemes(&[a, b]), 1);
+        assert_eq!(graphemes(&[b"a\r", b"\nb"]), 3);
+    }
+
+    #[test]
+    fn long_clusters() {
+        // regional indicators pair up from the start, so an odd one out is left at the end
+        let indicators = "\u{1f1e6}".repeat(BLOCK_SIZE / 2 + 1);
+        let blocks = indicators.as_bytes().chunks(4093).collect::<Vec<_>>();
+
+        assert_eq!(graphemes(&blocks), (BLOCK_SIZE / 4 + 1) as u64);
+
+        // a single cluster longer than a block
+        let marks = "a".to_string() + &"\u{301}".repeat(BLOCK_SIZE);
+        let blocks = marks.as_bytes().chunks(4093).collect::<Vec<_>>();
+
+        assert_eq!(graphemes(&blocks), 1);
+    }
+
+    fn max_line_length(args: &[&str], input: &[u8]) -> u64 {
+        count(&[&["-L"], args].concat(), &[input]).max_line_length
+    }
+
+    #[test]
+    fn tab_stops() {
+        assert_eq!(max_line_length(&[], b"a\tb\n"), 9);
+        assert_eq!(max_line_length(&[], b"\t\n"), 8);
+        assert_eq!(max_line_length(&["--tab-width=4"], b"ab\tc\n"), 5);
+        assert_eq!(max_line_length(&["--tab-width=4"], b"abcd\t\n"), 8);
+        assert_eq!(max_line_length(&["--tab-width=4"], b"\t\t"), 8);
+        assert_eq!(max_line_length(&["--tab-width=1"], b"a\tb\t"), 4);
+    }
+
+    #[test]
+    fn display_widths() {
+        let width = |input: &str| max_line_length(&[], input.as_bytes());
+
+        assert_eq!(width("\u{ff21}\u{ff22}\n"), 4);
+        assert_eq!(width("\u{6f22}\u{5b57}a\n"), 5);
+        assert_eq!(width("e\u{301}a\u{308}\n"), 2);
+        assert_eq!(width("a\x01\x7fb\u{200b}\n"), 2);
+        assert_eq!(max_line_length(&[], b"a\xFF\xE2\x82b\n"), 2);
+    }
+
+    #[test]
+    fn line_resets() {
+        // CRs and form feeds go back to the start of the line like GNU wc,
+        // a CRLF is just the end of the line
+        assert_eq!(max_line_length(&[], b"abc\rd\n"), 3);
+        assert_eq!(max_line_length(&[], b"ab\x0cc\n"), 2);
+        assert_eq!(max_line_length(&[], b"ab\rabcd"), 4);
+        assert_eq!(max_line_length(&[], b"abcd\r\nab\r\n"), 4);
+        // and tab stops start over with them
+        assert_eq!(
+            max_line_length(&["--tab-width=4"], b"abcdefghij\r\t\tx"),
+            10
+        );
+        assert_eq!(
+            max_line_length(&["--tab-width=4"], b"abcdefghij\x0c\t\tx"),
+            10
+        );
+        assert_eq!(max_line_length(&["--tab-width=4"], b"abcdefghij\t\tx"), 17);
+    }
+
+    /// lines, and whether the last one is missing its newline
+    fn lines(args: &[&str], input: &[u8]) -> (u64, u64) {
+        let statistics = count(&[&["-l", "--missing-newline"], args].concat(), &[input]);
+
+        (statistics.lines, statistics.missing_newline)
+    }
+
+    #[test]
+    fn posix_lines() {
+        assert_eq!(lines(&[], b""), (0, 0));
+        assert_eq!(lines(&[], b"a"), (0, 1));
+        assert_eq!(lines(&[], b"a\n"), (1, 0));
+        assert_eq!(lines(&[], b"a\nb"), (1, 1));
+        assert_eq!(lines(&[], b"\n\n\n"), (3, 0));
+        assert_eq!(lines(&[], b"a\r\nb\r"), (1, 1));
+    }
+
+    #[test]
+    fn partial_lines() {
+        let args = ["--count-partial-lines"];
+
+        assert_eq!(lines(&args, b""), (0, 0));
+        assert_eq!(lines(&args, b"a"), (1, 1));
+        assert_eq!(lines(&args, b"a\n"), (1, 0));
+        assert_eq!(lines(&args, b"a\nb"), (2, 1));
+        assert_eq!(lines(&args, b"a\n\r"), (2, 1));
+    }
+
+    /// lf, crlf, cr and mixed
+    fn endings(blocks: &[&[u8]]) -> [u64; 4] {
+        let statistics = count(&["--line-endings"], blocks);
+
+        [
+            statistics.lf,
+            statistics.crlf,
+            statistics.cr,
+            statistics.mixed_endings,
+        ]
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(endings(&[b""]), [0, 0, 0, 0]);
+        assert_eq!(endings(&[b"a\nb\n"]), [2, 0, 0, 0]);
+        assert_eq!(endings(&[b"a\r\nb\r\n"]), [0, 2, 0, 0]);
+        assert_eq!(endings(&[b"a\rb\r"]), [0, 0, 2, 0]);
+        assert_eq!(endings(&[b"a\r\nb\n"]), [1, 1, 0, 1]);
+        assert_eq!(endings(&[b"a\r\r\n\n\r"]), [1, 1, 2, 1]);
+        // a CRLF split between blocks is still one
+        assert_eq!(endings(&[b"a\r", b"\nb"]), [0, 1, 0, 0]);
+        assert_eq!(endings(&[b"a\r", b"b\n"]), [1, 0, 1, 1]);
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use ignore::{overrides::OverrideBuilder, WalkBuilder};
+
+use crate::{options::Options, quoting::quote};
+
+/// how much of a file is looked at to decide whether it's binary
+const BINARY_SNIFF_LEN: u64 = 8 * 1024;
+
+/// An iterator over the files to count, along with problems finding them
+pub type Names<'a> = Box<dyn Iterator<Item = anyhow::Result<Input>> + Send + 'a>;
+
+/// A problem with a file that was found while walking a directory,
+/// kept apart from the reason so that the file can be reported on its own
+#[derive(Debug)]
+pub struct PathError {
+    pub path: PathBuf,
+    pub error: anyhow::Error,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", quote(self.path.as_os_str()), self.error)
+    }
+}
+
+impl Error for PathError {}
+
+/// A file to count
+pub struct Input {
+    pub name: PathBuf,
+
+    /// how far below a directory that was recursed into this was found,
+    /// 0 for files that were named directly
+    pub depth: usize,
+}
+
+impl Input {
+    pub fn named(name: PathBuf) -> Self {
+        Self { name, depth: 0 }
+    }
+}
+
+/// The file names in a `--files0-from` list
+///
+/// names are read one at a time as they're needed,
+/// so the whole list never has to be held in memory
+pub struct Files0 {
+    reader: Box<dyn BufRead + Send>,
+
+    /// where the names come from, for error messages
+    source: PathBuf,
+
+    /// the number of names read so far
+    index: usize,
+    done: bool,
+}
+
+impl Files0 {
+    /// Opens a list of NUL-terminated file names, `-` meaning stdin
+    pub fn open(source: &Path) -> anyhow::Result<Self> {
+        let reader: Box<dyn BufRead + Send> = if source.as_os_str() == "-" {
+            Box::new(BufReader::new(std::io::stdin()))
+        } else {
+            Box::new(BufReader::new(File::open(source)?))
+        };
+
+        Ok(Self {
+            reader,
+            source: source.to_path_buf(),
+            index: 0,
+            done: false,
+        })
+    }
+}
+
+impl Iterator for Files0 {
+    type Item = anyhow::Result<Input>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut name = Vec::new();
+
+        match self.reader.read_until(0, &mut name) {
+            Ok(0) => {
+                self.done = true;
+
+                return None;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                self.done = true;
+
+                return Some(Err(anyhow!(
+                    "{}: read error: {e}",
+                    quote(self.source.as_os_str())
+                )));
+            }
+        }
+
+        self.index += 1;
+
+        // the last name doesn't have to be terminated
+        if name.last() == Some(&0) {
+            name.pop();
+        }
+
+        let (source, index) = (quote(self.source.as_os_str()), self.index);
+
+        if name.is_empty() {
+            return Some(Err(anyhow!(
+                "{source}:{index}: invalid zero-length file name"
+            )));
+        }
+
+        // stdin is already taken up by the list
+        if self.source.as_os_str() == "-" && name == b"-" {
+            return Some(Err(anyhow!(
+                "{source}:{index}: when reading file names from standard input, no file name of '-' allowed"
+            )));
+        }
+
+        Some(
+            bytes_to_path(name)
+                .map(Input::named)
+                .map_err(|_| anyhow!("{source}:{index}: file name is not valid utf-8")),
+        )
+    }
+}
+
+/// How directories are walked for `--recursive`
+pub struct Recursive {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    gitignore: bool,
+    skip_binary: bool,
+}
+
+impl Recursive {
+    pub fn new(options: &Options) -> anyhow::Result<Self> {
+        let recursive = Self {
+            include: options.include.clone(),
+            exclude: options.exclude.clone(),
+            gitignore: options.gitignore,
+            skip_binary: options.skip_binary,
+        };
+
+        // catch bad globs up front rather than once per directory
+        recursive.overrides(Path::new("."))?;
+
+        Ok(recursive)
+    }
+
+    /// The files under `root`, in a stable order
+    ///
+    /// anything that isn't a directory is passed through,
+    /// the globs only apply to what's found inside directories
+    pub fn files(&self, root: PathBuf) -> Names<'_> {
+        // stdin can't be walked
+        if root.as_os_str() == "-" {
+            return Box::new(std::iter::once(Ok(Input::named(root))));
+        }
+
+        let overrides = match self.overrides(&root) {
+            Ok(overrides) => overrides,
+            Err(e) => return Box::new(std::iter::once(Err(e))),
+        };
+
+        let gitignore = self.gitignore;
+
+        // everything is walked like `find` does (hidden files included),
+        // unless ignore files were asked for
+        let walk = WalkBuilder::new(&root)
+            .standard_filters(false)
+            .git_ignore(gitignore)
+            .git_global(gitignore)
+            .git_exclude(gitignore)
+            .parents(gitignore)
+            .require_git(false)
+            .overrides(overrides)
+            // the repository itself isn't part of what's ignored
+            .filter_entry(move |entry| !(gitignore && entry.file_name() == ".git"))
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+
+        Box::new(walk.filter_map(move |entry| {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(walk_error(e))),
+            };
+
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                return None;
+            }
+
+            if self.skip_binary && is_binary(entry.path()) {
+                return None;
+            }
+
+            let depth = entry.depth();
+
+            Some(Ok(Input {
+                name: entry.into_path(),
+                depth,
+            }))
+        }))
+    }
+
+    fn overrides(&self, root: &Path) -> anyhow::Result<ignore::overrides::Override> {
+        let mut overrides = OverrideBuilder::new(root);
+
+        for glob in &self.include {
+            overrides.add(glob)?;
+        }
+
+        for glob in &self.exclude {
+            overrides.add(&format!("!{glob}"))?;
+        }
+
+        Ok(overrides.build()?)
+    }
+}
+
+/// Whether a file looks binary, going by whether its start has a NUL byte in it,
+/// same as git and grep
+///
+/// files that can't be read aren't binary, so that the error is reported when counting them
+fn is_binary(path: &Path) -> bool {
+    let mut start = Vec::new();
+
+    File::open(path)
+        .and_then(|file| file.take(BINARY_SNIFF_LEN).read_to_end(&mut start))
+        .is_ok_and(|_| start.contains(&0))
+}
+
+/// Turns the bytes of a file name into a path,
+/// which can only fail where paths have to be unicode
+fn bytes_to_path(name: Vec<u8>) -> Result<PathBuf, Vec<u8>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+
+        Ok(OsString::from_vec(name).into())
+    }
+
+    #[cfg(not(unix))]
+    {
+        String::from_utf8(name)
+            .map(|name| OsString::from(name).into())
+            .map_err(|e| e.into_bytes())
+    }
+}
+
+/// Turns a walk error into a `path: reason` one, without the line and depth details
+fn walk_error(error: ignore::Error) -> anyhow::Error {
+    match error {
+        ignore::Error::WithPath { path, err } => PathError {
+            path,
+            error: walk_error(*err),
+        }
+        .into(),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            walk_error(*err)
+        }
+        ignore::Error::Io(e) => os_error(e).into(),
+        error => error.into(),
+    }
+}
+
+/// Digs the os error out of the io errors that walkdir makes,
+/// which otherwise say `IO error for operation on <path>` before the actual reason
+fn os_error(error: io::Error) -> io::Error {
+    if error.get_ref().is_none() {
+        return error;
+    }
+
+    let kind = error.kind();
+    let inner = error.into_inner().expect("checked above");
+
+    match inner.source().and_then(|e| e.downcast_ref::<io::Error>()) {
+        Some(e) => match e.raw_os_error() {
+            Some(code) => io::Error::from_raw_os_error(code),
+            None => io::Error::new(e.kind(), e.to_string()),
+        },
+        None => io::Error::new(kind, inner),
+    }
+}
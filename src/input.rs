@@ -0,0 +1,166 @@
+use std::{
+    fs::{File, Metadata},
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    counter::{Chunk, Counter, BLOCK_SIZE},
+    options::{InputMode, Options},
+    parallel, read_blocks, read_file, Statistics,
+};
+
+/// Counts a file, picking how to read it based on what kind of file it is
+///
+/// large regular files are split between the `jobs` (and memory-mapped for that, depending on `--io`),
+/// everything else is read in blocks
+pub fn count_file(
+    filename: &Path,
+    buffer: &mut [u8],
+    options: &Options,
+    jobs: usize,
+) -> anyhow::Result<Statistics> {
+    let mut file = File::open(filename)?;
+    let metadata = file.metadata()?;
+
+    // note: some special files (like in /proc) claim to be regular but empty,
+    // they have to be read to find out what's actually in them
+    let regular = metadata.is_file() && metadata.len() > 0;
+
+    // when only bytes are wanted there's no need to look at the contents
+    if options.only_bytes() {
+        if let Some(len) = known_len(&mut file, &metadata)? {
+            return Ok(Statistics::with_bytes(len));
+        }
+    }
+
+    let chunked = jobs > 1 && regular && metadata.len() >= parallel::CHUNKED_MIN_LEN;
+
+    // note: a mapped file that shrinks while it's counted kills the whole process (SIGBUS),
+    // taking the rest of the output with it, so it's only worth it for a single big file
+    let mmap = match options.io {
+        InputMode::Auto => chunked,
+        InputMode::Mmap => regular,
+        InputMode::Read => false,
+    };
+
+    if mmap {
+        // safety: the map is only read from, but if the file is truncated
+        // by another process while we're reading it, accessing the
+        // missing pages raises SIGBUS, see above
+        let map = unsafe { Mmap::map(&file)? };
+
+        if chunked {
+            return parallel::count_chunked(
+                map.len() as u64,
+                jobs,
+                options.splits_at_lines(),
+                |start, len, window| {
+                    let start = start as usize;
+
+                    window.extend_from_slice(&map[start..map.len().min(start + len)]);
+
+                    Ok(())
+                },
+                |start, end| {
+                    let mut counter = Counter::new(options);
+
+                    counter.update(&map[start as usize..end as usize]);
+
+                    Ok(counter.finish_chunk())
+                },
+            );
+        }
+
+        let mut counter = Counter::new(options);
+
+        counter.update(&map);
+
+        return Ok(counter.finish());
+    }
+
+    if chunked {
+        return parallel::count_chunked(
+            metadata.len(),
+            jobs,
+            options.splits_at_lines(),
+            |start, len, window| {
+                file.seek(SeekFrom::Start(start))?;
+                (&mut file).take(len as u64).read_to_end(window)?;
+
+                Ok(())
+            },
+            |start, end| count_range(filename, start, end, options),
+        );
+    }
+
+    read_file(&mut file, buffer, options)
+}
+
+/// Counts standard input, which skips reading too when only bytes are wanted
+/// and it's been redirected from a file
+pub fn count_stdin(buffer: &mut [u8], options: &Options) -> anyhow::Result<Statistics> {
+    #[cfg(unix)]
+    if options.only_bytes() {
+        use std::os::fd::AsFd;
+
+        // note: the duplicate shares the seek position with stdin,
+        // so whatever was already consumed before we got it isn't counted
+        let mut file = File::from(io::stdin().as_fd().try_clone_to_owned()?);
+        let metadata = file.metadata()?;
+
+        if let Some(len) = known_len(&mut file, &metadata)? {
+            return Ok(Statistics::with_bytes(len));
+        }
+    }
+
+    read_file(&mut io::stdin().lock(), buffer, options)
+}
+
+/// Finds how many bytes are left to read in a file without reading them, if it can be told
+///
+/// regular files know their size, and block devices can be seeked to the end,
+/// everything else (pipes, directories, ...) has to be read to find out
+fn known_len(file: &mut File, metadata: &Metadata) -> io::Result<Option<u64>> {
+    // note: empty ones are read anyway, see count_file
+    if metadata.is_file() && metadata.len() > 0 {
+        let position = file.stream_position()?;
+
+        return Ok(Some(metadata.len().saturating_sub(position)));
+    }
+
+    #[cfg(unix)]
+    if std::os::unix::fs::FileTypeExt::is_block_device(&metadata.file_type()) {
+        let position = file.stream_position()?;
+
+        match file.seek(SeekFrom::End(0))? {
+            end @ 1.. => return Ok(Some(end.saturating_sub(position))),
+            // devices that report no size might still have something to read
+            _ => {
+                file.seek(SeekFrom::Start(position))?;
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Counts the bytes from `start` up to `end` of a file
+fn count_range(filename: &Path, start: u64, end: u64, options: &Options) -> anyhow::Result<Chunk> {
+    // note: every part opens the file itself,
+    // since a cloned handle would share the seek position
+    let mut file = File::open(filename)?;
+
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut counter = Counter::new(options);
+    let mut buffer = vec![0; BLOCK_SIZE];
+
+    read_blocks(&mut file.take(end - start), &mut buffer, |block| {
+        counter.update(block)
+    })?;
+
+    Ok(counter.finish_chunk())
+}
@@ -1,86 +1,56 @@
 use std::{
-    fmt::{format, Arguments},
-    fs::File,
-    io::{BufRead, BufReader, Write},
+    fmt::Display,
+    io::{ErrorKind, Read},
     ops::Add,
     process::ExitCode,
 };
 
 use clap::Parser;
-use options::Options;
-
+use counter::Counter;
+use files::{Files0, Input, Names, PathError, Recursive};
+use options::{Options, Total};
+use output::Printer;
+use quoting::{quote, quote_always};
+use subtotals::Subtotals;
+
+mod counter;
+mod files;
+mod input;
 mod options;
+mod output;
+mod parallel;
+mod quoting;
+mod subtotals;
 
-// custom `write!()` macro that writes to a `FixedString`
-// it has a special case for writing string literals
-// to avoid the overhead of formatting
-//
-// the downside is that it doesn't support inline format arguments
-macro_rules! write_fixed {
-    ($dst:expr, $argzero:tt, $($arg:tt)+) => {
-        $dst.write_fmt(format_args!($argzero, $($arg)*))
-    };
-    ($dst:expr, $lit:literal) => {
-        $dst.write_lit($lit)
-    };
-}
-
-/// A fixed-size string that can be used to build a string without heap allocation
-pub struct FixedString<const N: usize> {
-    data: [u8; N],
-    len: usize,
-}
-
-impl<const N: usize> FixedString<N> {
-    pub fn new() -> Self {
-        Self {
-            data: [0; N],
-            len: 0,
-        }
-    }
-
-    /// this function creates a `&str` from the data
-    /// you must ensure that the data is valid utf-8
-    /// note: zero cost, as this is a mem::transmute
-    pub unsafe fn as_str_unchecked(&self) -> &str {
-        // note: zero cost, this is a mem::transmute
-        std::str::from_utf8_unchecked(&self.data[..self.len])
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Statistics {
+    bytes: u64,
+    chars: u64,
 
-    pub fn as_bytes(&self) -> &[u8] {
-        &self.data[..self.len]
-    }
+    /// extended grapheme clusters (UAX #29)
+    graphemes: u64,
 
-    /// this will panic if there's not enough space
-    pub fn push_str_unchecked(&mut self, s: &str) {
-        self.data[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+    lines: u64,
+    words: u64,
+    max_line_length: u64,
 
-        self.len += s.len();
-    }
+    /// 1 for a file that doesn't end in a newline,
+    /// and the number of such files once added up
+    missing_newline: u64,
 
-    pub fn write_lit(&mut self, lit: &str) {
-        self.push_str_unchecked(lit);
-    }
+    /// the number of each kind of line ending,
+    /// where LFs and CRs are only the ones that aren't part of a CRLF
+    lf: u64,
+    crlf: u64,
+    cr: u64,
 
-    // compatibility with the `write!()` macro
-    pub fn write_fmt(&mut self, args: Arguments) {
-        // actually format the string
-        // we could optimize for the no-args case
-        // but we have a custom `write!()` macro
-        // that calls into `write_lit()` instead for that case
-        let fmt = format(args);
+    /// 1 for a file with more than one kind of line ending,
+    /// and the number of such files once added up
+    mixed_endings: u64,
 
-        self.push_str_unchecked(&fmt);
-    }
-}
-
-#[derive(Debug)]
-struct Statistics {
-    bytes: u32,
-    chars: u32,
-    lines: u32,
-    words: u32,
-    max_line_length: u16,
+    /// set when adding up statistics overflowed,
+    /// in which case the counts are stuck at `u64::MAX`
+    saturated: bool,
 }
 
 impl Statistics {
@@ -88,43 +58,25 @@ impl Statistics {
         Self {
             bytes: 0,
             chars: 0,
+            graphemes: 0,
             lines: 0,
             words: 0,
             max_line_length: 0,
+            missing_newline: 0,
+            lf: 0,
+            crlf: 0,
+            cr: 0,
+            mixed_endings: 0,
+            saturated: false,
         }
     }
 
-    pub fn print(&self, opts: &Options, filename: &str) {
-        let mut s = FixedString::<64>::new();
-
-        if opts.bytes {
-            write_fixed!(&mut s, "{:8} ", self.bytes);
-        }
-
-        if opts.chars {
-            write_fixed!(&mut s, "{:8} ", self.chars);
-        }
-
-        if opts.lines {
-            write_fixed!(&mut s, "{:8} ", self.lines);
-        }
-
-        if opts.words {
-            write_fixed!(&mut s, "{:8} ", self.words);
-        }
-
-        if opts.max_line_length {
-            write_fixed!(&mut s, "{:8} ", self.max_line_length);
+    /// Statistics with only the byte count filled in
+    pub fn with_bytes(bytes: u64) -> Self {
+        Self {
+            bytes,
+            ..Self::new()
         }
-
-        // note: zero cost, this is a mem::transmute
-        // safety: we're sure that the string is valid utf-8
-        // because we're building it from valid utf-8 strings
-        let s = unsafe { s.as_str_unchecked() };
-
-        // `filename` is an unknown length
-        // so we can't add it to the `FixedString`
-        println!("{s}{filename}");
     }
 }
 
@@ -132,121 +84,263 @@ impl Add for Statistics {
     type Output = Self;
 
     fn add(self, other: Self) -> Self {
+        let mut saturated = self.saturated || other.saturated;
+
+        let mut sum = |a: u64, b: u64| {
+            a.checked_add(b).unwrap_or_else(|| {
+                saturated = true;
+
+                u64::MAX
+            })
+        };
+
         Self {
-            bytes: self.bytes + other.bytes,
-            chars: self.chars + other.chars,
-            lines: self.lines + other.lines,
-            words: self.words + other.words,
+            bytes: sum(self.bytes, other.bytes),
+            chars: sum(self.chars, other.chars),
+            graphemes: sum(self.graphemes, other.graphemes),
+            lines: sum(self.lines, other.lines),
+            words: sum(self.words, other.words),
             max_line_length: self.max_line_length.max(other.max_line_length),
+            missing_newline: sum(self.missing_newline, other.missing_newline),
+            lf: sum(self.lf, other.lf),
+            crlf: sum(self.crlf, other.crlf),
+            cr: sum(self.cr, other.cr),
+            mixed_endings: sum(self.mixed_endings, other.mixed_endings),
+            saturated,
         }
     }
 }
 
-fn read_file<R: BufRead>(reader: &mut R, buffer: &mut String) -> anyhow::Result<Statistics> {
-    let mut statistics = Statistics::new();
-
-    while reader.read_line(buffer)? > 0 {
-        statistics.lines += 1;
-        statistics.bytes += buffer.len() as u32;
-        statistics.chars += buffer.chars().count() as u32;
-        statistics.words += buffer.split_whitespace().count() as u32;
-        statistics.max_line_length = statistics.max_line_length.max(buffer.len() as u16);
+fn read_file<R: Read>(
+    reader: &mut R,
+    buffer: &mut [u8],
+    options: &Options,
+) -> anyhow::Result<Statistics> {
+    let mut counter = Counter::new(options);
 
-        buffer.clear();
-    }
+    read_blocks(reader, buffer, |block| counter.update(block))?;
 
-    Ok(statistics)
+    Ok(counter.finish())
 }
 
-fn print_header(options: &Options) -> std::io::Result<()> {
-    let mut s = FixedString::<54>::new();
-
-    if options.bytes {
-        write_fixed!(&mut s, "   bytes")
+/// Reads all of `reader` through `buffer`, handing each block to `f`
+fn read_blocks<R: Read>(
+    reader: &mut R,
+    buffer: &mut [u8],
+    mut f: impl FnMut(&[u8]),
+) -> std::io::Result<()> {
+    loop {
+        let n = match reader.read(buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        f(&buffer[..n]);
     }
+}
 
-    if options.chars {
-        write_fixed!(&mut s, "    chars")
-    }
+/// Prints why an input couldn't be counted, in `wc2: path: reason` form
+fn report(what: impl Display, error: &anyhow::Error) {
+    eprintln!("wc2: {what}: {}", reason(error));
+}
 
-    if options.lines {
-        write_fixed!(&mut s, "    lines")
-    }
+fn reason(error: &anyhow::Error) -> String {
+    let mut reason = error.to_string();
 
-    if options.words {
-        write_fixed!(&mut s, "    words")
+    // io errors end in " (os error N)", which is just noise here
+    if let Some(i) = reason.rfind(" (os error ") {
+        reason.truncate(i);
     }
 
-    if options.max_line_length {
-        write_fixed!(&mut s, "      max")
-    }
+    reason
+}
 
-    if options.filename {
-        write_fixed!(&mut s, " filename")
-    }
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => code,
+        // whatever was reading the output stopped (like `wc2 * | head -1`),
+        // which isn't worth a message, and gets the same status as being killed by SIGPIPE
+        Err(e) if is_broken_pipe(&e) => ExitCode::from(128 + 13),
+        Err(e) => {
+            eprintln!("wc2: write error: {}", reason(&e));
 
-    write_fixed!(s, "\n");
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    // note: `s` is valid utf-8
-    // because we're building it from valid utf-8 strings
-    std::io::stdout().write_all(s.as_bytes())
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == ErrorKind::BrokenPipe)
 }
 
-fn main() -> anyhow::Result<ExitCode> {
-    let options = Options::try_parse()?;
+/// Counts everything and prints the output
+///
+/// note: problems with the inputs are reported as they come up,
+/// so the only errors that end up here are from writing the output
+fn run() -> anyhow::Result<ExitCode> {
+    let mut options = Options::parse();
+
+    options.select_counts();
 
     // we only actually need the total statistics
     // if a total was requested and there are multiple files
     // however, there might not be a good way to avoid storing it anyway
     let mut total = Statistics::new();
 
-    // this buffer is reused for reading lines
-    let mut buffer = String::new();
+    // failing to count one file doesn't stop the others from being counted,
+    // but it's still reflected in the exit code
+    let mut failed = false;
+
+    // the number of files there were, to decide whether to print the total,
+    // including ones that couldn't be counted since the total still covers the rest
+    let mut seen = 0;
+
+    let names: Names = if let Some(source) = &options.files0_from {
+        match Files0::open(source) {
+            Ok(names) => Box::new(names),
+            Err(e) => {
+                report(
+                    format_args!(
+                        "cannot open {} for reading",
+                        quote_always(source.as_os_str())
+                    ),
+                    &e,
+                );
+
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    } else if options.files.is_empty() {
+        // like grep, recursing with nothing to recurse into means the current directory
+        let default = if options.recursive { "." } else { "-" };
 
-    if options.files.is_empty() {
-        let mut reader = std::io::stdin().lock();
+        Box::new(std::iter::once(Ok(Input::named(default.into()))))
+    } else {
+        Box::new(
+            options
+                .files
+                .iter()
+                .map(|name| Ok(Input::named(name.clone()))),
+        )
+    };
 
-        let statistics = read_file(&mut reader, &mut buffer)?;
+    let recursive = if options.recursive {
+        match Recursive::new(&options) {
+            Ok(recursive) => Some(recursive),
+            Err(e) => {
+                eprintln!("wc2: {}", reason(&e));
 
-        if !options.no_header {
-            print_header(&options)?;
+                return Ok(ExitCode::FAILURE);
+            }
         }
+    } else {
+        None
+    };
 
-        statistics.print(&options, "-");
+    let inputs: Names = match &recursive {
+        Some(recursive) => Box::new(names.flat_map(|name| -> Names {
+            match name {
+                Ok(name) => recursive.files(name.name),
+                Err(e) => Box::new(std::iter::once(Err(e))),
+            }
+        })),
+        None => names,
+    };
+
+    let mut subtotals = options.subtotals.then(|| Subtotals::new(options.max_depth));
+
+    let mut printer = Printer::new(&options);
+
+    // the threads either count several files side by side or split up a single one,
+    // never both at once, which would make up to jobs² of them
+    let single = options.files.len() <= 1 && options.files0_from.is_none() && !options.recursive;
+    let (jobs, split_jobs) = if single {
+        (1, options.jobs())
     } else {
-        let mut header = !options.no_header;
+        (options.jobs(), 1)
+    };
+
+    let count = |input: &anyhow::Result<Input>, buffer: &mut [u8]| match input {
+        Ok(input) if input.name.as_os_str() == "-" => input::count_stdin(buffer, &options),
+        Ok(input) => input::count_file(&input.name, buffer, &options, split_jobs),
+        // there's nothing to count, this is reported below
+        Err(_) => Ok(Statistics::new()),
+    };
+
+    parallel::count_all(inputs, jobs, count, |input, statistics| {
+        seen += 1;
+
+        let input = match input {
+            Ok(input) => input,
+            // files that were found but can't be gone into get reported like any other file
+            Err(e) if e.is::<PathError>() => {
+                let e = e.downcast::<PathError>().expect("checked above");
 
-        for filename in &options.files {
-            let statistics = if filename == "-" {
-                let mut reader = std::io::stdin().lock();
+                report(quote(e.path.as_os_str()), &e.error);
+                printer.error(Some(&e.path), &reason(&e.error))?;
 
-                read_file(&mut reader, &mut buffer)
-            } else {
-                let file = File::open(&filename)?;
-                let mut reader = BufReader::new(file);
+                failed = true;
+
+                return Ok(());
+            }
+            Err(e) => {
+                let reason = reason(&e);
 
-                read_file(&mut reader, &mut buffer)
-            }?;
+                eprintln!("wc2: {reason}");
+                printer.error(None, &reason)?;
 
-            // print the header exactly once
-            //
-            // Q: why not print it at the start of the prgram?
-            // A: because stdin might be interactive
-            if header {
-                print_header(&options)?;
+                failed = true;
 
-                header = false;
+                return Ok(());
             }
+        };
 
-            statistics.print(&options, &filename);
+        let statistics = match statistics {
+            Ok(statistics) => statistics,
+            Err(e) => {
+                report(quote(input.name.as_os_str()), &e);
+                printer.error(Some(&input.name), &reason(&e))?;
 
-            total = total + statistics;
+                failed = true;
+
+                return Ok(());
+            }
+        };
+
+        // directories that this file isn't in are finished, and come before it
+        if let Some(subtotals) = &mut subtotals {
+            subtotals.add(&input.name, input.depth, &statistics, |dir, subtotal| {
+                printer.directory(dir, subtotal)
+            })?;
         }
+
+        printer.file(&input.name, &statistics)?;
+
+        total = total + statistics;
+
+        Ok(())
+    })?;
+
+    if let Some(subtotals) = &mut subtotals {
+        subtotals.finish(|dir, subtotal| printer.directory(dir, subtotal))?;
     }
 
-    if options.total && options.files.len() > 1 {
-        total.print(&options, "total");
+    if options.total != Total::Never && total.saturated {
+        eprintln!(
+            "wc2: warning: the total is too large to represent, some counts are capped at {}",
+            u64::MAX
+        );
     }
 
-    Ok(ExitCode::SUCCESS)
+    printer.finish(&total, seen)?;
+
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
 }
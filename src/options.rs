@@ -1,30 +1,429 @@
-use clap::ArgAction;
+use std::{io::IsTerminal, num::NonZeroUsize, path::PathBuf};
+
+use clap::{ArgAction, ValueEnum};
+use regex::bytes::Regex;
+
+/// How to count invalid utf-8 sequences towards `chars`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InvalidUtf8 {
+    /// don't count invalid sequences as characters (like GNU wc)
+    Skip,
+
+    /// count each invalid sequence as one replacement character
+    Replace,
+
+    /// count each byte of an invalid sequence as a character
+    Byte,
+}
+
+impl InvalidUtf8 {
+    /// the number of characters that an invalid sequence of `len` bytes counts as
+    pub fn chars(self, len: usize) -> usize {
+        match self {
+            Self::Skip => 0,
+            Self::Replace => 1,
+            Self::Byte => len,
+        }
+    }
+}
+
+/// How regular files are read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputMode {
+    /// read files in blocks, except for a single large file split between --jobs which is memory-mapped
+    Auto,
+
+    /// always memory-map regular files
+    Mmap,
+
+    /// always read files in blocks
+    Read,
+}
+
+/// How the counts are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// aligned columns, like wc
+    Table,
+
+    /// a single json document, with every file and the total
+    Json,
+
+    /// a json object per line, for every file and then the total
+    Jsonl,
+
+    /// comma-separated values, with a header row
+    Csv,
+
+    /// tab-separated values, with a header row
+    Tsv,
+}
+
+/// When to print the total
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Total {
+    /// for more than one file, and always in json
+    Auto,
+
+    Always,
+
+    /// only the total, without the rows for files and directories
+    Only,
+
+    Never,
+}
+
+/// Which units `--human` uses for byte counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Human {
+    /// powers of 1000: k, M, G, ...
+    Si,
+
+    /// powers of 1024: Ki, Mi, Gi, ...
+    Iec,
+}
+
+/// How file names are written in tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum QuotingStyle {
+    /// as they are
+    Literal,
+
+    /// in single quotes when a shell would need them, with control characters shown as ?
+    Shell,
+
+    /// in single quotes when a shell would need them, with control characters as $'\n'
+    ShellEscape,
+
+    /// in double quotes, with C escapes
+    C,
+}
+
+/// What counts as a word
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WordMode {
+    /// anything between unicode whitespace
+    Whitespace,
+
+    /// anything between ascii whitespace, which is what isspace() is in the C locale
+    Posix,
+
+    /// runs of letters and digits split at UAX #29 word boundaries,
+    /// so punctuation isn't a word and CJK text is split up
+    Unicode,
+}
+
+/// One of the counters that can be shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Count {
+    Lines,
+    Words,
+    Chars,
+    Graphemes,
+    Bytes,
+    MaxLineLength,
+    MissingNewline,
+    LineEndings,
+}
 
 #[derive(Debug, clap::Parser)]
 pub struct Options {
-    pub files: Vec<String>,
+    pub files: Vec<PathBuf>,
 
-    #[clap(short = 'b', long, default_value_t = true, action = ArgAction::SetFalse)]
-    pub bytes: bool,
+    /// read the files to count from the NUL-terminated names in F, - meaning stdin
+    #[clap(long, value_name = "F", conflicts_with = "files")]
+    pub files0_from: Option<PathBuf>,
 
-    #[clap(short = 'c', long, default_value_t = true, action = ArgAction::SetFalse)]
-    pub chars: bool,
+    /// count the files in directories, and their subdirectories
+    #[clap(short = 'r', long)]
+    pub recursive: bool,
+
+    /// only count files matching this glob when recursing
+    #[clap(long, value_name = "GLOB", requires = "recursive")]
+    pub include: Vec<String>,
+
+    /// don't count files or enter directories matching this glob when recursing
+    #[clap(long, value_name = "GLOB", requires = "recursive")]
+    pub exclude: Vec<String>,
+
+    /// skip files ignored by .gitignore files when recursing
+    #[clap(long, requires = "recursive")]
+    pub gitignore: bool,
+
+    /// skip binary files (ones with a NUL byte near the start) when recursing
+    #[clap(long, requires = "recursive")]
+    pub skip_binary: bool,
+
+    /// also print a subtotal for every directory when recursing, after what's in it
+    #[clap(long, requires = "recursive")]
+    pub subtotals: bool,
+
+    /// only print subtotals for directories this many levels down,
+    /// the ones recursed into being 0
+    #[clap(long, value_name = "N", requires = "subtotals")]
+    pub max_depth: Option<usize>,
 
-    #[clap(short = 'l', long, default_value_t = true, action = ArgAction::SetFalse)]
+    /// print the newline counts
+    #[clap(short = 'l', long)]
     pub lines: bool,
 
-    #[clap(short = 'w', long, default_value_t = true, action = ArgAction::SetFalse)]
+    /// print the word counts
+    #[clap(short = 'w', long)]
     pub words: bool,
 
-    #[clap(short = 'L', long, default_value_t = true, action = ArgAction::SetFalse)]
+    /// print the character counts
+    #[clap(short = 'm', long)]
+    pub chars: bool,
+
+    /// print the grapheme cluster counts, which is characters as people see them
+    #[clap(long)]
+    pub graphemes: bool,
+
+    /// print the byte counts
+    #[clap(short = 'c', long)]
+    pub bytes: bool,
+
+    /// print the maximum line length, in display columns
+    /// (CRs and form feeds go back to the start of the line, same as GNU wc)
+    #[clap(short = 'L', long)]
     pub max_line_length: bool,
 
+    /// print 1 for files that don't end in a newline,
+    /// which makes the total the number of files that don't
+    #[clap(long)]
+    pub missing_newline: bool,
+
+    /// print the number of LF, CRLF and bare CR line endings,
+    /// and 1 for files with more than one kind (totalled as the number of such files)
+    #[clap(long)]
+    pub line_endings: bool,
+
+    /// count a last line without a newline at the end as a line too,
+    /// rather than counting newlines like POSIX wc
+    #[clap(long)]
+    pub count_partial_lines: bool,
+
+    /// show the counters wc has (lines, words, chars, bytes and max-line-length) except these
+    #[clap(
+        long,
+        visible_alias = "exclude-counts",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub hide: Vec<Count>,
+
     #[clap(long, default_value_t = true, action = ArgAction::SetFalse)]
     pub filename: bool,
 
-    #[clap(long, default_value_t = true, action = ArgAction::SetFalse)]
-    pub total: bool,
+    /// when to print the total
+    #[clap(long, value_enum, value_name = "WHEN", default_value_t = Total::Auto)]
+    pub total: Total,
 
     #[clap(long, default_value_t = false)]
     pub no_header: bool,
+
+    /// how to print the counts, in json the bytes of names that aren't valid utf-8
+    /// are written as lone surrogates \udc80 to \udcff (like python's surrogateescape)
+    #[clap(long, value_enum, default_value_t = Format::Table)]
+    pub format: Format,
+
+    /// print counts like 1.2M in tables, bytes can be in powers of 1024 with =iec
+    #[clap(
+        long,
+        value_enum,
+        value_name = "UNITS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "si",
+        conflicts_with = "thousands"
+    )]
+    pub human: Option<Human>,
+
+    /// group the digits of counts in tables by thousands, with SEP between them
+    #[clap(
+        long,
+        value_name = "SEP",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ","
+    )]
+    pub thousands: Option<String>,
+
+    /// how to write file names in tables,
+    /// shell-escape when printing to a terminal and literal otherwise by default
+    #[clap(long, value_enum, value_name = "STYLE")]
+    pub quoting_style: Option<QuotingStyle>,
+
+    /// print every row of the table as soon as it's counted,
+    /// with the column width guessed from the sizes of the files
+    /// (always the case with --files0-from and --recursive)
+    #[clap(long)]
+    pub streaming: bool,
+
+    /// count up to this many files at once, or split a single large file into this many parts,
+    /// 0 means one per CPU
+    #[clap(short = 'j', long, default_value_t = 1)]
+    pub jobs: usize,
+
+    /// how to read regular files, pipes and special files are always read
+    #[clap(long, value_enum, default_value_t = InputMode::Auto)]
+    pub io: InputMode,
+
+    /// the distance between tab stops, for the maximum line length
+    #[clap(long, value_name = "N", default_value_t = 8, value_parser = clap::value_parser!(u64).range(1..))]
+    pub tab_width: u64,
+
+    /// what counts as a word
+    #[clap(long, value_enum, default_value_t = WordMode::Whitespace)]
+    pub word_mode: WordMode,
+
+    /// characters that separate words as well as whitespace, like -_/
+    #[clap(long, value_name = "CHARS", allow_hyphen_values = true)]
+    pub word_delimiters: Option<String>,
+
+    /// count the matches of this regex as the words instead, a line at a time
+    #[clap(
+        long,
+        value_name = "REGEX",
+        value_parser = Regex::new,
+        conflicts_with_all = ["word_mode", "word_delimiters"]
+    )]
+    pub word_regex: Option<Regex>,
+
+    /// how to count invalid utf-8 sequences towards chars
+    #[clap(long, value_enum, default_value_t = InvalidUtf8::Skip)]
+    pub invalid_utf8: InvalidUtf8,
+}
+
+impl Options {
+    /// the number of worker threads to count files (or parts of a single file) on
+    pub fn jobs(&self) -> usize {
+        match self.jobs {
+            0 => std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            jobs => jobs,
+        }
+    }
+
+    /// how to write file names in tables, when it wasn't picked it depends on where they go
+    pub fn quoting_style(&self) -> QuotingStyle {
+        self.quoting_style.unwrap_or_else(|| {
+            if std::io::stdout().is_terminal() {
+                QuotingStyle::ShellEscape
+            } else {
+                QuotingStyle::Literal
+            }
+        })
+    }
+
+    /// whether large inputs have to be split into parts at newlines rather than anywhere,
+    /// because something is counted per line or can't be stitched back together
+    pub fn splits_at_lines(&self) -> bool {
+        self.max_line_length
+            || self.graphemes
+            || self.word_mode == WordMode::Unicode
+            || self.word_regex.is_some()
+    }
+
+    /// whether table rows are printed as they come instead of held back until the end
+    ///
+    /// note: lists of files and directory trees can be as long as they like,
+    /// so their rows aren't kept around just to size the columns
+    pub fn streaming(&self) -> bool {
+        self.streaming || self.files0_from.is_some() || self.recursive
+    }
+
+    /// whether bytes are the only thing that needs counting
+    pub fn only_bytes(&self) -> bool {
+        self.bytes
+            && !(self.lines
+                || self.words
+                || self.chars
+                || self.graphemes
+                || self.max_line_length
+                || self.missing_newline
+                || self.line_endings)
+    }
+
+    /// Works out which counters to show, the same way as wc:
+    /// with no counters selected, lines, words and bytes are shown,
+    /// otherwise exactly the selected ones are
+    ///
+    /// `--hide` starts from the five counters wc has when none are selected,
+    /// the ones added since have to be asked for so that its output doesn't change
+    /// (and doesn't get slower) every time there's a new one
+    pub fn select_counts(&mut self) {
+        let selected = self.lines
+            || self.words
+            || self.chars
+            || self.graphemes
+            || self.bytes
+            || self.max_line_length
+            || self.missing_newline
+            || self.line_endings;
+
+        if !selected {
+            let all = !self.hide.is_empty();
+
+            self.lines = true;
+            self.words = true;
+            self.chars = all;
+            self.bytes = true;
+            self.max_line_length = all;
+        }
+
+        for count in &self.hide {
+            match count {
+                Count::Lines => self.lines = false,
+                Count::Words => self.words = false,
+                Count::Chars => self.chars = false,
+                Count::Graphemes => self.graphemes = false,
+                Count::Bytes => self.bytes = false,
+                Count::MaxLineLength => self.max_line_length = false,
+                Count::MissingNewline => self.missing_newline = false,
+                Count::LineEndings => self.line_endings = false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn selected(args: &[&str]) -> [bool; 8] {
+        let mut options = Options::parse_from(["wc2"].iter().chain(args));
+        options.select_counts();
+
+        [
+            options.lines,
+            options.words,
+            options.chars,
+            options.graphemes,
+            options.bytes,
+            options.max_line_length,
+            options.missing_newline,
+            options.line_endings,
+        ]
+    }
+
+    #[test]
+    fn default_counts() {
+        let [l, w, m, g, c, max, nl, le] = selected(&[]);
+
+        assert!(l && w && c && !(m || g || max || nl || le));
+    }
+
+    #[test]
+    fn hidden_counts() {
+        // only the counters wc has, whatever gets added later
+        let [l, w, m, g, c, max, nl, le] = selected(&["--hide=words"]);
+
+        assert!(l && m && c && max && !(w || g || nl || le));
+
+        let [l, w, m, g, c, max, nl, le] = selected(&["--hide=lines", "--graphemes", "-w"]);
+
+        assert!(w && g && !(l || m || c || max || nl || le));
+    }
 }
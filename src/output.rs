@@ -0,0 +1,707 @@
+use std::{
+    ffi::OsStr,
+    fmt::Write as _,
+    io::{StdoutLock, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    options::{Format, Human, Options, QuotingStyle, Total},
+    quoting::push_quoted,
+    Statistics,
+};
+
+/// Prints the rows of output in whichever `--format` was asked for
+///
+/// rows have to be given in the order they should appear,
+/// and `finish` has to be called at the end to close off the output
+///
+/// tables are held back until the end so that the columns can be as wide as
+/// the widest count, unless they are streamed (see `Options::streaming`)
+pub struct Printer<'a> {
+    options: &'a Options,
+
+    /// whether anything has been printed yet,
+    /// the header (or the start of the json document) is only printed before the first row
+    ///
+    /// Q: why not print it at the start of the prgram?
+    /// A: because stdin might be interactive
+    started: bool,
+
+    /// the number of json objects in the `files` array so far, to know where commas go
+    rows: usize,
+
+    /// the rows of a table that haven't been printed yet
+    table: Vec<(PathBuf, Statistics)>,
+
+    /// how wide the columns of a table are
+    width: usize,
+
+    /// stdout is locked once rather than for every row
+    out: StdoutLock<'static>,
+
+    /// the row being built, reused so that rows don't each need their own allocation
+    ///
+    /// note: this is bytes rather than a string, because file names don't have to be utf-8
+    line: Vec<u8>,
+
+    /// how file names are written in tables
+    quoting: QuotingStyle,
+}
+
+impl<'a> Printer<'a> {
+    pub fn new(options: &'a Options) -> Self {
+        Self {
+            options,
+            started: false,
+            rows: 0,
+            table: Vec::new(),
+            width: if options.streaming() {
+                streaming_width(options)
+            } else {
+                0
+            },
+            out: std::io::stdout().lock(),
+            line: Vec::new(),
+            quoting: options.quoting_style(),
+        }
+    }
+
+    pub fn file(&mut self, filename: &Path, statistics: &Statistics) -> std::io::Result<()> {
+        self.row("file", filename, statistics)
+    }
+
+    /// the subtotal for a directory
+    pub fn directory(&mut self, dirname: &Path, statistics: &Statistics) -> std::io::Result<()> {
+        self.row("directory", dirname, statistics)
+    }
+
+    /// an input that couldn't be counted
+    ///
+    /// note: these are already on stderr, this only adds them to json output
+    pub fn error(&mut self, filename: Option<&Path>, reason: &str) -> std::io::Result<()> {
+        if !matches!(self.options.format, Format::Json | Format::Jsonl) {
+            return Ok(());
+        }
+
+        self.start()?;
+
+        let mut object = String::from("{");
+
+        if let Some(filename) = filename {
+            object.push_str("\"file\": ");
+            push_json_name(&mut object, filename.as_os_str());
+            object.push_str(", ");
+        }
+
+        object.push_str("\"error\": ");
+        push_json_string(&mut object, reason);
+        object.push('}');
+
+        self.object(&object)
+    }
+
+    /// Prints the total (if there should be one) and closes off the output
+    ///
+    /// by default a table only gets a total for more than one file,
+    /// but json always has one so that its shape doesn't change
+    pub fn finish(&mut self, total: &Statistics, inputs: usize) -> std::io::Result<()> {
+        self.finish_output(total, inputs)?;
+
+        // whatever is still buffered has to make it out for errors like a full disk to show up
+        self.out.flush()
+    }
+
+    fn finish_output(&mut self, total: &Statistics, inputs: usize) -> std::io::Result<()> {
+        let options = self.options;
+
+        let print_total = match options.total {
+            Total::Auto => inputs > 1 || matches!(options.format, Format::Json | Format::Jsonl),
+            Total::Always | Total::Only => true,
+            Total::Never => false,
+        };
+
+        match options.format {
+            Format::Table => {
+                let total = print_total.then_some(total);
+                let table = std::mem::take(&mut self.table);
+
+                if !options.streaming() {
+                    let counts = table.iter().map(|(_, statistics)| statistics);
+
+                    self.width = table_width(options, counts.chain(total));
+                }
+
+                for (name, statistics) in &table {
+                    self.start()?;
+
+                    push_table_row(&mut self.line, options, self.width, statistics);
+                    push_quoted(&mut self.line, name.as_os_str(), self.quoting);
+                    self.line.push(b'\n');
+                    self.write_line()?;
+                }
+
+                if let Some(total) = total {
+                    self.start()?;
+
+                    push_table_row(&mut self.line, options, self.width, total);
+                    self.line.extend_from_slice(b"total\n");
+                    self.write_line()?;
+                }
+
+                Ok(())
+            }
+            Format::Csv | Format::Tsv => {
+                if !print_total {
+                    return Ok(());
+                }
+
+                self.start()?;
+
+                push_csv_row(&mut self.line, options, total, "total", b"total");
+                self.write_line()
+            }
+            Format::Json => {
+                self.start()?;
+
+                let mut end = String::from(if self.rows > 0 { "\n  ]" } else { "]" });
+
+                if print_total {
+                    end.push_str(",\n  \"total\": {");
+                    push_json_counts(&mut end, options, total);
+                    end.push('}');
+                }
+
+                end.push_str("\n}\n");
+
+                self.out.write_all(end.as_bytes())
+            }
+            Format::Jsonl => {
+                if !print_total {
+                    return Ok(());
+                }
+
+                let mut object = String::from("{\"total\": {");
+                push_json_counts(&mut object, options, total);
+                object.push_str("}}");
+
+                self.object(&object)
+            }
+        }
+    }
+
+    fn row(&mut self, kind: &str, name: &Path, statistics: &Statistics) -> std::io::Result<()> {
+        if self.options.total == Total::Only {
+            return Ok(());
+        }
+
+        // a buffered table is started once it's known how wide it is
+        if self.options.format != Format::Table || self.options.streaming() {
+            self.start()?;
+        }
+
+        match self.options.format {
+            Format::Table if self.options.streaming() => {
+                push_table_row(&mut self.line, self.options, self.width, statistics);
+                push_quoted(&mut self.line, name.as_os_str(), self.quoting);
+                self.line.push(b'\n');
+
+                return self.write_line();
+            }
+            Format::Table => {
+                self.table.push((name.to_owned(), *statistics));
+
+                return Ok(());
+            }
+            Format::Csv | Format::Tsv => {
+                let name = name.as_os_str().as_encoded_bytes();
+
+                push_csv_row(&mut self.line, self.options, statistics, kind, name);
+
+                return self.write_line();
+            }
+            Format::Json | Format::Jsonl => {}
+        }
+
+        let mut object = String::new();
+
+        // json has to be unicode, so names that aren't utf-8 get escaped
+        let _ = write!(object, "{{\"{kind}\": ");
+        push_json_name(&mut object, name.as_os_str());
+
+        let mut counts = String::new();
+        push_json_counts(&mut counts, self.options, statistics);
+
+        if !counts.is_empty() {
+            object.push_str(", ");
+            object.push_str(&counts);
+        }
+
+        object.push('}');
+
+        self.object(&object)
+    }
+
+    fn start(&mut self) -> std::io::Result<()> {
+        if self.started {
+            return Ok(());
+        }
+
+        self.started = true;
+
+        match self.options.format {
+            Format::Table if !self.options.no_header => {
+                push_table_header(&mut self.line, self.options, self.width);
+                self.write_line()
+            }
+            Format::Csv | Format::Tsv if !self.options.no_header => {
+                let delimiter = csv_delimiter(self.options);
+
+                for (name, _) in counts_of(self.options, &Statistics::new()) {
+                    self.line.extend_from_slice(name.as_bytes());
+                    self.line.push(delimiter);
+                }
+
+                self.line.extend_from_slice(b"type");
+                self.line.push(delimiter);
+                self.line.extend_from_slice(b"filename\n");
+                self.write_line()
+            }
+            Format::Json => self.out.write_all(b"{\n  \"files\": ["),
+            _ => Ok(()),
+        }
+    }
+
+    /// writes out the row that was built up in `line`, leaving it empty for the next one
+    fn write_line(&mut self) -> std::io::Result<()> {
+        let result = self.out.write_all(&self.line);
+
+        self.line.clear();
+
+        result
+    }
+
+    /// writes a json object as the next element of the `files` array,
+    /// or on a line of its own
+    fn object(&mut self, object: &str) -> std::io::Result<()> {
+        let stdout = &mut self.out;
+
+        if self.options.format == Format::Json {
+            let separator = if self.rows > 0 { ",\n    " } else { "\n    " };
+
+            stdout.write_all(separator.as_bytes())?;
+            stdout.write_all(object.as_bytes())?;
+        } else {
+            stdout.write_all(object.as_bytes())?;
+            stdout.write_all(b"\n")?;
+        }
+
+        self.rows += 1;
+
+        Ok(())
+    }
+}
+
+/// The narrowest the columns can be to fit every count, and the labels if there's a header
+fn table_width<'a>(options: &Options, counts: impl Iterator<Item = &'a Statistics>) -> usize {
+    let labels = label_width(options);
+
+    counts
+        .flat_map(|statistics| counts_of(options, statistics))
+        .map(|(name, count)| table_count(options, name, count).chars().count())
+        .fold(labels, usize::max)
+}
+
+/// The column width for `--streaming`, which has to be known before anything is counted
+///
+/// no count can be more than the number of bytes (or the total more than all of them),
+/// so the sizes of the files are enough to go by, same as GNU wc
+fn streaming_width(options: &Options) -> usize {
+    // wide enough for counts up to 99,999,999
+    const UNKNOWN_WIDTH: usize = 8;
+
+    // human-readable counts are never wider than `1023Ki`
+    if options.human.is_some() {
+        return 6.max(label_width(options));
+    }
+
+    // lists of files and directories would have to be gone through twice
+    if options.files.is_empty() || options.files0_from.is_some() || options.recursive {
+        return UNKNOWN_WIDTH.max(label_width(options));
+    }
+
+    let mut size = 0u64;
+
+    for name in &options.files {
+        match std::fs::metadata(name) {
+            Ok(metadata) if metadata.is_file() => size = size.saturating_add(metadata.len()),
+            // files that can't be counted don't get a row
+            Err(_) => {}
+            // pipes and such can't be sized up front
+            Ok(_) => return UNKNOWN_WIDTH.max(label_width(options)),
+        }
+    }
+
+    // except for line lengths, where a tab is a single byte but several columns
+    if options.max_line_length {
+        size = size.saturating_mul(options.tab_width);
+    }
+
+    let width = table_count(options, "bytes", size).chars().count();
+
+    width.max(label_width(options))
+}
+
+/// The widest label of the selected counters, or 0 without a header
+fn label_width(options: &Options) -> usize {
+    if options.no_header {
+        return 0;
+    }
+
+    counts_of(options, &Statistics::new())
+        .map(|(name, _)| table_label(name).len())
+        .max()
+        .unwrap_or(0)
+}
+
+/// what a counter is called in the header of a table, which is narrower than elsewhere
+fn table_label(name: &str) -> &str {
+    match name {
+        "max_line_length" => "max",
+        "missing_newline" => "noeol",
+        "mixed_endings" => "mixed",
+        name => name,
+    }
+}
+
+/// Appends the header of a table, with the labels right-aligned over their columns
+fn push_table_header(s: &mut Vec<u8>, options: &Options, width: usize) {
+    let mut first = true;
+
+    for (name, _) in counts_of(options, &Statistics::new()) {
+        if !first {
+            s.push(b' ');
+        }
+
+        first = false;
+
+        let _ = write!(s, "{:>width$}", table_label(name));
+    }
+
+    if options.filename {
+        if !first {
+            s.push(b' ');
+        }
+
+        s.extend_from_slice(b"filename");
+    }
+
+    s.push(b'\n');
+}
+
+/// Appends the counts of a row of a table, every counter followed by a space,
+/// which leaves the name to be added after
+fn push_table_row(s: &mut Vec<u8>, options: &Options, width: usize, statistics: &Statistics) {
+    for (label, count) in counts_of(options, statistics) {
+        let _ = write!(s, "{:>width$} ", table_count(options, label, count));
+    }
+}
+
+/// A count the way it's shown in a table, which is affected by `--human` and `--thousands`
+fn table_count(options: &Options, name: &str, count: u64) -> String {
+    if let Some(human) = options.human {
+        // only bytes have a choice of units, everything else goes in thousands
+        return match human {
+            Human::Iec if name == "bytes" => human_count(count, 1024, &IEC_UNITS),
+            _ => human_count(count, 1000, &SI_UNITS),
+        };
+    }
+
+    let digits = count.to_string();
+
+    let Some(separator) = &options.thousands else {
+        return digits;
+    };
+
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3 * separator.len());
+
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push_str(separator);
+        }
+
+        grouped.push(digit);
+    }
+
+    grouped
+}
+
+const SI_UNITS: [&str; 6] = ["k", "M", "G", "T", "P", "E"];
+const IEC_UNITS: [&str; 6] = ["Ki", "Mi", "Gi", "Ti", "Pi", "Ei"];
+
+/// Shortens `count` to a few digits and a unit, like `1.2M` or `34k`,
+/// with a decimal only for numbers below 10
+fn human_count(count: u64, base: u64, units: &[&str]) -> String {
+    if count < base {
+        return count.to_string();
+    }
+
+    let base = base as f64;
+    let mut value = count as f64;
+    let mut unit = 0;
+
+    while value >= base && unit < units.len() {
+        value /= base;
+        unit += 1;
+    }
+
+    // 999.9k would round up to 1000k, which is better off as 1.0M
+    if value.round() >= base && unit < units.len() {
+        value /= base;
+        unit += 1;
+    }
+
+    let unit = units[unit - 1];
+
+    if value < 9.95 {
+        format!("{value:.1}{unit}")
+    } else {
+        format!("{value:.0}{unit}")
+    }
+}
+
+/// The selected counters, named the way they are in json and csv output
+fn counts_of(
+    options: &Options,
+    statistics: &Statistics,
+) -> impl Iterator<Item = (&'static str, u64)> {
+    [
+        (options.lines, "lines", statistics.lines),
+        (options.words, "words", statistics.words),
+        (options.chars, "chars", statistics.chars),
+        (options.graphemes, "graphemes", statistics.graphemes),
+        (options.bytes, "bytes", statistics.bytes),
+        (
+            options.max_line_length,
+            "max_line_length",
+            statistics.max_line_length,
+        ),
+        (
+            options.missing_newline,
+            "missing_newline",
+            statistics.missing_newline,
+        ),
+        (options.line_endings, "lf", statistics.lf),
+        (options.line_endings, "crlf", statistics.crlf),
+        (options.line_endings, "cr", statistics.cr),
+        (
+            options.line_endings,
+            "mixed_endings",
+            statistics.mixed_endings,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(selected, name, count)| selected.then_some((name, count)))
+}
+
+/// Appends the selected counters as the members of a json object
+fn push_json_counts(s: &mut String, options: &Options, statistics: &Statistics) {
+    let mut first = true;
+
+    for (name, count) in counts_of(options, statistics) {
+        if !first {
+            s.push_str(", ");
+        }
+
+        first = false;
+
+        let _ = write!(s, "\"{name}\": {count}");
+    }
+
+    // totals that overflowed are capped, so they're marked as such
+    if statistics.saturated {
+        if !first {
+            s.push_str(", ");
+        }
+
+        s.push_str("\"saturated\": true");
+    }
+}
+
+/// Appends `value` as a quoted json string
+fn push_json_string(s: &mut String, value: &str) {
+    s.push('"');
+    push_json_chars(s, value);
+    s.push('"');
+}
+
+/// Appends a file name as a quoted json string
+///
+/// json strings can only hold unicode, so bytes that aren't valid utf-8
+/// are written as lone surrogates `\udc80` to `\udcff` (like python's surrogateescape),
+/// which no valid name can turn into
+fn push_json_name(s: &mut String, name: &OsStr) {
+    s.push('"');
+
+    for chunk in name.as_encoded_bytes().utf8_chunks() {
+        push_json_chars(s, chunk.valid());
+
+        for &byte in chunk.invalid() {
+            let _ = write!(s, "\\u{:04x}", 0xDC00 + byte as u32);
+        }
+    }
+
+    s.push('"');
+}
+
+/// Appends `value` escaped for the inside of a json string
+fn push_json_chars(s: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\r' => s.push_str("\\r"),
+            '\t' => s.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(s, "\\u{:04x}", c as u32);
+            }
+            c => s.push(c),
+        }
+    }
+}
+
+fn csv_delimiter(options: &Options) -> u8 {
+    match options.format {
+        Format::Tsv => b'\t',
+        _ => b',',
+    }
+}
+
+/// Appends a csv (or tsv) row of the selected counters followed by the kind of row and the name
+fn push_csv_row(
+    s: &mut Vec<u8>,
+    options: &Options,
+    statistics: &Statistics,
+    kind: &str,
+    name: &[u8],
+) {
+    let delimiter = csv_delimiter(options);
+
+    for (_, count) in counts_of(options, statistics) {
+        let _ = write!(s, "{count}");
+        s.push(delimiter);
+    }
+
+    // so that directory subtotals and the total can be told apart from files (and left out of sums)
+    s.extend_from_slice(kind.as_bytes());
+    s.push(delimiter);
+
+    // names are only quoted when they need to be, with quotes doubled (RFC 4180)
+    if name
+        .iter()
+        .any(|b| [delimiter, b'"', b'\n', b'\r'].contains(b))
+    {
+        s.push(b'"');
+
+        for &b in name {
+            if b == b'"' {
+                s.push(b'"');
+            }
+
+            s.push(b);
+        }
+
+        s.push(b'"');
+    } else {
+        s.extend_from_slice(name);
+    }
+
+    s.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn human_si() {
+        let si = |count| human_count(count, 1000, &SI_UNITS);
+
+        assert_eq!(si(999), "999");
+        assert_eq!(si(1000), "1.0k");
+        assert_eq!(si(9_949), "9.9k");
+        assert_eq!(si(9_950), "10k");
+        assert_eq!(si(999_499), "999k");
+        assert_eq!(si(999_500), "1.0M");
+        assert_eq!(si(999_950), "1.0M");
+        assert_eq!(si(1_250_000), "1.2M");
+        assert_eq!(si(u64::MAX), "18E");
+    }
+
+    #[test]
+    fn human_iec() {
+        let iec = |count| human_count(count, 1024, &IEC_UNITS);
+
+        assert_eq!(iec(1023), "1023");
+        assert_eq!(iec(1024), "1.0Ki");
+        assert_eq!(iec(1536), "1.5Ki");
+        assert_eq!(iec(10 * 1024), "10Ki");
+        assert_eq!(iec(1023 * 1024), "1023Ki");
+        assert_eq!(iec(1023 * 1024 + 512), "1.0Mi");
+        assert_eq!(iec(u64::MAX), "16Ei");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn json_names() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let json = |name: &[u8]| {
+            let mut s = String::new();
+
+            push_json_name(&mut s, OsStr::from_bytes(name));
+
+            s
+        };
+
+        assert_eq!(json(b"caf\xC3\xA9"), "\"caf\u{e9}\"");
+        assert_eq!(json(b"a\"b\\\n"), r#""a\"b\\\n""#);
+        assert_eq!(json(b"bad\xFF\xC3"), r#""bad\udcff\udcc3""#);
+        // different from the replacement character itself
+        assert_ne!(json(b"\xFF"), json("\u{fffd}".as_bytes()));
+    }
+
+    fn csv_row(format: &str, name: &[u8]) -> String {
+        let mut options = Options::parse_from(["wc2", "-lc", "--format", format]);
+        options.select_counts();
+
+        let mut s = Vec::new();
+
+        push_csv_row(&mut s, &options, &Statistics::with_bytes(3), "file", name);
+
+        String::from_utf8(s).unwrap()
+    }
+
+    #[test]
+    fn csv_quoting() {
+        assert_eq!(csv_row("csv", b"plain.txt"), "0,3,file,plain.txt\n");
+        assert_eq!(csv_row("csv", b"a,b"), "0,3,file,\"a,b\"\n");
+        assert_eq!(
+            csv_row("csv", b"say \"hi\""),
+            "0,3,file,\"say \"\"hi\"\"\"\n"
+        );
+        assert_eq!(csv_row("csv", b"two\nlines"), "0,3,file,\"two\nlines\"\n");
+        assert_eq!(csv_row("csv", b"cr\r"), "0,3,file,\"cr\r\"\n");
+        assert_eq!(csv_row("csv", b"tab\there"), "0,3,file,tab\there\n");
+    }
+
+    #[test]
+    fn tsv_quoting() {
+        assert_eq!(csv_row("tsv", b"tab\there"), "0\t3\tfile\t\"tab\there\"\n");
+        assert_eq!(csv_row("tsv", b"a,b"), "0\t3\tfile\ta,b\n");
+        assert_eq!(csv_row("tsv", b"\""), "0\t3\tfile\t\"\"\"\"\n");
+    }
+}
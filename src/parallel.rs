@@ -0,0 +1,173 @@
+use std::{
+    collections::BTreeMap,
+    panic,
+    sync::{mpsc, Mutex, PoisonError},
+    thread,
+};
+
+use crate::{
+    counter::{self, Chunk, BLOCK_SIZE},
+    Statistics,
+};
+
+/// inputs at least this large are split up and counted on several threads
+pub const CHUNKED_MIN_LEN: u64 = 64 * 1024 * 1024;
+
+/// how far past where a part would end to look for a newline to end it at instead
+const LINE_SEARCH_LEN: usize = 1024 * 1024;
+
+/// Counts every input on a pool of `jobs` threads
+///
+/// `each` is called on this thread with the results in the same order as `inputs`,
+/// no matter which order the workers finish in, so output stays deterministic
+///
+/// with a single job everything happens on this thread, without spawning anything
+pub fn count_all<T, I, C, F>(inputs: I, jobs: usize, count: C, mut each: F) -> anyhow::Result<()>
+where
+    T: Send,
+    I: Iterator<Item = T> + Send,
+    C: Fn(&T, &mut [u8]) -> anyhow::Result<Statistics> + Sync,
+    F: FnMut(T, anyhow::Result<Statistics>) -> anyhow::Result<()>,
+{
+    if jobs <= 1 {
+        let mut buffer = vec![0; BLOCK_SIZE];
+
+        for input in inputs {
+            let statistics = count(&input, &mut buffer);
+
+            each(input, statistics)?;
+        }
+
+        return Ok(());
+    }
+
+    // workers pull inputs one at a time as they become free,
+    // so a few large files don't hold up the rest
+    let inputs = Mutex::new(inputs.enumerate());
+
+    thread::scope(|scope| {
+        // note: the receiver lives in here so that it's dropped as soon as `each` fails,
+        // which makes the workers' sends fail and stops them early
+        let (sender, receiver) = mpsc::channel();
+
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let inputs = &inputs;
+            let count = &count;
+
+            scope.spawn(move || {
+                // each worker reuses its own buffer
+                let mut buffer = vec![0; BLOCK_SIZE];
+
+                loop {
+                    // the lock is only held while taking the next input, not while counting
+                    let next = inputs.lock().unwrap_or_else(PoisonError::into_inner).next();
+
+                    let Some((index, input)) = next else {
+                        break;
+                    };
+
+                    let statistics = count(&input, &mut buffer);
+
+                    if sender.send((index, input, statistics)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        drop(sender);
+
+        // results that arrived before the ones that come before them
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+
+        for (index, input, statistics) in receiver {
+            pending.insert(index, (input, statistics));
+
+            while let Some((input, statistics)) = pending.remove(&next) {
+                each(input, statistics)?;
+
+                next += 1;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Counts a single input of `len` bytes by splitting it into `jobs` parts
+/// that are counted at the same time and then merged back together
+///
+/// `read_window` fills the vector with up to the given number of bytes starting at an offset,
+/// which is used to find where to split, and `count` counts the bytes in `start..end`
+///
+/// parts end at the start of a character, or after a newline with `at_lines`,
+/// in which case there might be fewer of them if lines are very long
+pub fn count_chunked<R, C>(
+    len: u64,
+    jobs: usize,
+    at_lines: bool,
+    mut read_window: R,
+    count: C,
+) -> anyhow::Result<Statistics>
+where
+    R: FnMut(u64, usize, &mut Vec<u8>) -> std::io::Result<()>,
+    C: Fn(u64, u64) -> anyhow::Result<Chunk> + Sync,
+{
+    let mut bounds = vec![0];
+    let mut window = Vec::new();
+
+    for i in 1..jobs as u64 {
+        let offset = len * i / jobs as u64;
+
+        window.clear();
+
+        let split = if at_lines {
+            read_window(offset, LINE_SEARCH_LEN, &mut window)?;
+
+            match counter::line_split_point(&window) {
+                Some(split) => offset + split as u64,
+                None => continue,
+            }
+        } else {
+            // look around the offset to avoid splitting a character in two
+            let start = offset.saturating_sub(3);
+
+            read_window(start, 7, &mut window)?;
+
+            start + counter::split_point(&window, (offset - start) as usize) as u64
+        };
+
+        if split > *bounds.last().unwrap() && split < len {
+            bounds.push(split);
+        }
+    }
+
+    bounds.push(len);
+
+    thread::scope(|scope| {
+        let workers = bounds
+            .windows(2)
+            .map(|range| {
+                let (start, end) = (range[0], range[1]);
+                let count = &count;
+
+                scope.spawn(move || count(start, end))
+            })
+            .collect::<Vec<_>>();
+
+        let mut merged: Option<Chunk> = None;
+
+        for worker in workers {
+            let chunk = worker.join().unwrap_or_else(|e| panic::resume_unwind(e))?;
+
+            merged = Some(match merged {
+                Some(merged) => merged.merge(chunk),
+                None => chunk,
+            });
+        }
+
+        Ok(merged.map_or_else(Statistics::new, Chunk::finish))
+    })
+}
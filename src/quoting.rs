@@ -0,0 +1,208 @@
+use std::{ffi::OsStr, io::Write as _};
+
+use crate::options::QuotingStyle;
+
+/// Appends `name` the way `style` says to, so that odd file names can't mess up the output
+pub fn push_quoted(s: &mut Vec<u8>, name: &OsStr, style: QuotingStyle) {
+    let name = name.as_encoded_bytes();
+
+    match style {
+        QuotingStyle::Literal => s.extend_from_slice(name),
+        QuotingStyle::Shell | QuotingStyle::ShellEscape if !needs_quotes(name) => {
+            s.extend_from_slice(name)
+        }
+        QuotingStyle::Shell => push_shell(s, name, false),
+        QuotingStyle::ShellEscape => push_shell(s, name, true),
+        QuotingStyle::C => push_c(s, name),
+    }
+}
+
+/// Quotes `name` for a message on stderr, like GNU's quotef
+///
+/// messages go to the terminal whatever `--quoting-style` says,
+/// so names in them are always escaped
+pub fn quote(name: &OsStr) -> String {
+    let mut s = Vec::new();
+
+    push_quoted(&mut s, name, QuotingStyle::ShellEscape);
+
+    // note: anything that isn't utf-8 has been escaped by now
+    String::from_utf8_lossy(&s).into_owned()
+}
+
+/// Like `quote`, but in quotes even when it wouldn't need them, like GNU's quoteaf
+pub fn quote_always(name: &OsStr) -> String {
+    let mut s = Vec::new();
+
+    push_shell(&mut s, name.as_encoded_bytes(), true);
+
+    String::from_utf8_lossy(&s).into_owned()
+}
+
+/// A character of a name, or a byte that isn't part of valid utf-8
+enum Unit {
+    Char(char),
+    Byte(u8),
+}
+
+fn units(name: &[u8]) -> impl Iterator<Item = Unit> + '_ {
+    name.utf8_chunks().flat_map(|chunk| {
+        let chars = chunk.valid().chars().map(Unit::Char);
+        let bytes = chunk.invalid().iter().map(|&b| Unit::Byte(b));
+
+        chars.chain(bytes)
+    })
+}
+
+/// whether a name means something else to a shell as it is
+fn needs_quotes(name: &[u8]) -> bool {
+    name.is_empty()
+        || units(name).any(|unit| match unit {
+            Unit::Char(c) => !(c.is_alphanumeric() || "%+,-./:=@_".contains(c)),
+            Unit::Byte(_) => true,
+        })
+}
+
+/// Appends `name` in single quotes,
+/// with control characters (and bytes that aren't utf-8) either as `$'\n'` escapes or just as `?`
+fn push_shell(s: &mut Vec<u8>, name: &[u8], escape: bool) {
+    s.push(b'\'');
+
+    for unit in units(name) {
+        match unit {
+            // a quote can't be escaped inside quotes, so they're closed around it
+            Unit::Char('\'') => s.extend_from_slice(b"'\\''"),
+            Unit::Char(c) if !c.is_control() => push_char(s, c),
+            unit if escape => {
+                s.extend_from_slice(b"'$'");
+                push_escaped(s, unit);
+                s.extend_from_slice(b"''");
+            }
+            _ => s.push(b'?'),
+        }
+    }
+
+    s.push(b'\'');
+
+    // `'a'$'\n'''` is the same as `'a'$'\n'`
+    if s.ends_with(b"'''") {
+        s.truncate(s.len() - 2);
+    }
+}
+
+/// Appends `name` as a C string literal
+fn push_c(s: &mut Vec<u8>, name: &[u8]) {
+    s.push(b'"');
+
+    for unit in units(name) {
+        match unit {
+            Unit::Char('"') => s.extend_from_slice(b"\\\""),
+            Unit::Char('\\') => s.extend_from_slice(b"\\\\"),
+            Unit::Char(c) if !c.is_control() => push_char(s, c),
+            unit => push_escaped(s, unit),
+        }
+    }
+
+    s.push(b'"');
+}
+
+fn push_char(s: &mut Vec<u8>, c: char) {
+    s.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+/// Appends a control character or a stray byte as a backslash escape,
+/// octal bytes if there's no name for it
+fn push_escaped(s: &mut Vec<u8>, unit: Unit) {
+    let escape: &[u8] = match unit {
+        Unit::Char('\x07') => b"\\a",
+        Unit::Char('\x08') => b"\\b",
+        Unit::Char('\t') => b"\\t",
+        Unit::Char('\n') => b"\\n",
+        Unit::Char('\x0b') => b"\\v",
+        Unit::Char('\x0c') => b"\\f",
+        Unit::Char('\r') => b"\\r",
+        Unit::Char(c) => {
+            for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                let _ = write!(s, "\\{byte:03o}");
+            }
+
+            return;
+        }
+        Unit::Byte(byte) => {
+            let _ = write!(s, "\\{byte:03o}");
+
+            return;
+        }
+    };
+
+    s.extend_from_slice(escape);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quoted(name: &str, style: QuotingStyle) -> String {
+        let mut s = Vec::new();
+
+        push_quoted(&mut s, OsStr::new(name), style);
+
+        String::from_utf8(s).unwrap()
+    }
+
+    #[test]
+    fn shell() {
+        let shell = |name| quoted(name, QuotingStyle::Shell);
+
+        assert_eq!(shell("plain.txt"), "plain.txt");
+        assert_eq!(shell("café"), "café");
+        assert_eq!(shell("a b"), "'a b'");
+        assert_eq!(shell("a\nb"), "'a?b'");
+        assert_eq!(shell("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn shell_escape() {
+        let escape = |name| quoted(name, QuotingStyle::ShellEscape);
+
+        assert_eq!(escape("a\nb"), r"'a'$'\n''b'");
+        assert_eq!(escape("it's"), r"'it'\''s'");
+        // nothing's left open at the end, which would leave an empty '' behind
+        assert_eq!(escape("a\n"), r"'a'$'\n'");
+        assert_eq!(escape("x'"), r"'x'\'");
+        assert_eq!(escape("''"), r"''\'''\'");
+        assert_eq!(escape("\t\n"), r"''$'\t'''$'\n'");
+    }
+
+    #[test]
+    fn c() {
+        let c = |name| quoted(name, QuotingStyle::C);
+
+        assert_eq!(c("plain"), r#""plain""#);
+        assert_eq!(c("a\"b\\"), r#""a\"b\\""#);
+        assert_eq!(c("tab\t\x01"), r#""tab\t\001""#);
+    }
+
+    #[test]
+    fn messages() {
+        assert_eq!(quote(OsStr::new("plain")), "plain");
+        assert_eq!(quote(OsStr::new("e\x1b[31mvil")), r"'e'$'\033''[31mvil'");
+        assert_eq!(quote_always(OsStr::new("plain")), "'plain'");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn invalid_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut s = Vec::new();
+
+        push_quoted(
+            &mut s,
+            OsStr::from_bytes(b"bad\xFF"),
+            QuotingStyle::ShellEscape,
+        );
+
+        assert_eq!(s, br"'bad'$'\377'");
+    }
+}
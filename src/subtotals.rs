@@ -0,0 +1,153 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::Statistics;
+
+/// Adds up the statistics of every directory that was recursed into, like `du`
+///
+/// files come in the order they were walked in, so a directory is finished
+/// as soon as a file outside of it shows up, at which point its subtotal is handed back,
+/// which means directories come after everything in them
+///
+/// note: directories without any files that were counted don't get a subtotal
+pub struct Subtotals {
+    /// the directories that the last file was in, outermost first
+    open: Vec<(PathBuf, Statistics)>,
+
+    /// the deepest level of directories to hand back, the one recursed into being 0
+    max_depth: Option<usize>,
+}
+
+impl Subtotals {
+    pub fn new(max_depth: Option<usize>) -> Self {
+        Self {
+            open: Vec::new(),
+            max_depth,
+        }
+    }
+
+    /// Adds a file that was found `depth` levels down,
+    /// calling `done` with every directory that's now finished
+    pub fn add(
+        &mut self,
+        name: &Path,
+        depth: usize,
+        statistics: &Statistics,
+        mut done: impl FnMut(&Path, &Statistics) -> io::Result<()>,
+    ) -> io::Result<()> {
+        // the directories the file is in, from the one that was recursed into down
+        let mut dirs = name.ancestors().skip(1).take(depth).collect::<Vec<_>>();
+        dirs.reverse();
+
+        // files named directly (depth 0) close everything
+        let shared = self
+            .open
+            .iter()
+            .zip(&dirs)
+            .take_while(|((open, _), dir)| open == *dir)
+            .count();
+
+        self.close(shared, &mut done)?;
+
+        for dir in &dirs[shared..] {
+            self.open.push((dir.to_path_buf(), Statistics::new()));
+        }
+
+        for (_, subtotal) in &mut self.open {
+            *subtotal = *subtotal + *statistics;
+        }
+
+        Ok(())
+    }
+
+    /// Finishes every directory, once there are no more files
+    pub fn finish(
+        &mut self,
+        mut done: impl FnMut(&Path, &Statistics) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.close(0, &mut done)
+    }
+
+    /// Finishes the directories past the first `keep`, innermost first
+    fn close(
+        &mut self,
+        keep: usize,
+        done: &mut impl FnMut(&Path, &Statistics) -> io::Result<()>,
+    ) -> io::Result<()> {
+        while self.open.len() > keep {
+            let depth = self.open.len() - 1;
+            let (dir, subtotal) = self.open.pop().unwrap();
+
+            if self.max_depth.is_none_or(|max| depth <= max) {
+                done(&dir, &subtotal)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// records a subtotal as `dir=bytes`
+    fn print(printed: &mut Vec<String>) -> impl FnMut(&Path, &Statistics) -> io::Result<()> + '_ {
+        |dir, subtotal| {
+            printed.push(format!("{}={}", dir.display(), subtotal.bytes));
+
+            Ok(())
+        }
+    }
+
+    /// what gets printed for `files` (as name, depth and bytes), in order
+    fn walk(files: &[(&str, usize, u64)], max_depth: Option<usize>) -> Vec<String> {
+        let mut subtotals = Subtotals::new(max_depth);
+        let mut printed = Vec::new();
+
+        for &(name, depth, bytes) in files {
+            let statistics = Statistics::with_bytes(bytes);
+
+            subtotals
+                .add(Path::new(name), depth, &statistics, print(&mut printed))
+                .unwrap();
+
+            printed.push(name.to_string());
+        }
+
+        subtotals.finish(print(&mut printed)).unwrap();
+
+        printed
+    }
+
+    const FILES: &[(&str, usize, u64)] = &[
+        ("a/x", 1, 1),
+        ("a/b/y", 2, 2),
+        ("a/z", 1, 4),
+        ("c", 0, 8),
+        ("d/w", 1, 16),
+        ("e/v", 1, 32),
+    ];
+
+    #[test]
+    fn closed_after_their_files() {
+        let printed = walk(FILES, None);
+
+        assert_eq!(
+            printed,
+            ["a/x", "a/b/y", "a/b=2", "a/z", "a=7", "c", "d/w", "d=16", "e/v", "e=32"]
+        );
+    }
+
+    #[test]
+    fn max_depth() {
+        let printed = walk(FILES, Some(0));
+
+        assert_eq!(
+            printed,
+            ["a/x", "a/b/y", "a/z", "a=7", "c", "d/w", "d=16", "e/v", "e=32"]
+        );
+    }
+}